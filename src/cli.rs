@@ -0,0 +1,363 @@
+//! The `call` and `repl` subcommands: run tools directly against the configured roots, without
+//! an MCP client, e.g. to reproduce a failing call from the server log. And `replay`, which runs
+//! a whole session from the server log again.
+
+use clap::Args;
+use rs_filesystem::mcp::config::AccessMode;
+use rs_filesystem::mcp::output;
+use rs_filesystem::mcp::patch::unified_diff;
+use rs_filesystem::mcp::tools::all_tools;
+use rs_filesystem::mcp::tools::tools_list;
+use rs_filesystem::replay;
+use rs_filesystem::FilesystemServer;
+use rs_filesystem::FilesystemServerBuilder;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::io::AsyncBufReadExt;
+
+#[derive(Args, Debug)]
+pub struct CallArgs {
+    /// Name of the tool, as in `--tools`
+    tool: String,
+    /// An argument, repeatable. Values of non-string arguments are parsed as JSON (numbers,
+    /// booleans); a repeated list argument collects its values.
+    #[arg(long = "arg", value_name = "KEY=VALUE")]
+    args: Vec<String>,
+    /// All arguments as a JSON object, e.g. copied from the server log; `--arg` adds to it
+    #[arg(long, value_name = "OBJECT")]
+    json: Option<String>,
+    /// Print the whole JSON-RPC response instead of the text of the result
+    #[arg(long, default_value = "false")]
+    raw: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    /// Server log with the session, as written by the server (`MCP_LOG_FILE_PATH`)
+    log: PathBuf,
+    /// The directory tree as it was when the session started. The session runs on a copy of it,
+    /// which is the only root.
+    #[arg(long, value_name = "DIR")]
+    tree: PathBuf,
+    /// Where the tree was when the session was recorded, if it has moved since; paths in the log
+    /// are relative to this
+    #[arg(long, value_name = "DIR")]
+    recorded_root: Option<PathBuf>,
+    /// Keep the copy of the tree after the replay, to look at what the session did
+    #[arg(long, default_value = "false")]
+    keep: bool,
+}
+
+const REPL_HELP: &str = "\
+Enter a tool call as `<tool> KEY=VALUE ...` or `<tool> {JSON arguments}`, or a JSON-RPC message
+to send it as is. `tools` lists the tools and `exit` quits.";
+
+/// Call a tool once and print the result. Returns the exit code: 1 when the call failed.
+pub async fn call(server: &FilesystemServer, args: CallArgs) -> i32 {
+    // notifications such as log messages would get mixed into the output
+    output::sessions_only();
+    let arguments = match build_arguments(&args.tool, &args.args, args.json.as_deref()) {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    if run_tool(server, 1, &args.tool, arguments, args.raw).await {
+        0
+    } else {
+        1
+    }
+}
+
+/// Read tool calls from stdin until EOF or `exit`, keeping the server's state (such as the
+/// working directory) between them
+pub async fn repl(server: &FilesystemServer) -> i32 {
+    output::sessions_only();
+    println!("{}", REPL_HELP);
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut id = 0;
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        let Ok(Some(line)) = lines.next_line().await else {
+            break;
+        };
+        let line = line.trim();
+        match line {
+            "" => continue,
+            "exit" | "quit" => break,
+            "help" => {
+                println!("{}", REPL_HELP);
+                continue;
+            }
+            "tools" => {
+                if let Ok(result) = tools_list(None).await {
+                    for tool in result.tools {
+                        println!("{}: {}", tool.name, tool.description.unwrap_or_default());
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+        id += 1;
+        if line.starts_with('{') || line.starts_with('[') {
+            match serde_json::from_str::<Value>(line) {
+                Ok(message) => {
+                    if let Some(response) = server.handle(message).await {
+                        print_response(&response, true);
+                    }
+                }
+                Err(e) => eprintln!("Invalid JSON: {}", e),
+            }
+            continue;
+        }
+        let (tool, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim_start();
+        let arguments = if rest.starts_with('{') {
+            build_arguments(tool, &[], Some(rest))
+        } else {
+            split_words(rest).and_then(|pairs| build_arguments(tool, &pairs, None))
+        };
+        match arguments {
+            Ok(arguments) => {
+                run_tool(server, id, tool, arguments, false).await;
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    0
+}
+
+/// Replay the session in a server log against a copy of `--tree` and print the responses that
+/// differ from the recorded ones. Returns the exit code: 1 when some differ, 2 when the replay
+/// can't run.
+pub async fn replay(builder: FilesystemServerBuilder, args: ReplayArgs) -> i32 {
+    output::sessions_only();
+    let log = match fs::read_to_string(&args.log) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", args.log.display(), e);
+            return 2;
+        }
+    };
+    let exchanges = replay::read_log(&log);
+    let recorded_root = args.recorded_root.as_ref().unwrap_or(&args.tree);
+    let recorded_root = recorded_root.canonicalize().unwrap_or_else(|_| recorded_root.clone());
+
+    // a new directory only the current user can enter, so nobody else can plant files in it
+    let mut workspace = tempfile::Builder::new();
+    workspace.prefix("rs_filesystem-replay-");
+    #[cfg(unix)]
+    workspace.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o700));
+    let workspace = match workspace.tempdir() {
+        Ok(workspace) => workspace,
+        Err(e) => {
+            eprintln!("Failed to create a directory for the replay: {}", e);
+            return 2;
+        }
+    };
+    let copy = workspace.path().join("tree");
+    if let Err(e) = replay::copy_tree(&args.tree, &copy) {
+        eprintln!("{}", e);
+        return 2;
+    }
+    let copy = copy.canonicalize().unwrap_or(copy);
+
+    // the copy is the only root: directories in the env variable would let the replay reach
+    // outside of it. The replay's own log entries must not end up in the log being replayed.
+    let builder = builder
+        .root(&copy, AccessMode::ReadWrite)
+        .env_roots(false)
+        .log_file(workspace.path().join("rs_filesystem.logs.jsonl"))
+        .audit_log_file(workspace.path().join("rs_filesystem.audit.jsonl"));
+    let exit_code = match builder.build() {
+        Ok(server) => {
+            let report = replay::replay(&server, &exchanges, &recorded_root, &copy).await;
+            print_report(&report);
+            if report.differences.is_empty() {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            2
+        }
+    };
+    if args.keep {
+        println!("The replayed tree and its logs are in {}", workspace.keep().display());
+    }
+    exit_code
+}
+
+fn print_report(report: &replay::Report) {
+    for difference in &report.differences {
+        let message = &difference.message;
+        let name = message["params"]["name"].as_str().or(message["method"].as_str()).unwrap_or("batch");
+        println!("line {}: {}", difference.line, name);
+        let recorded = serde_json::to_string_pretty(&difference.recorded).unwrap_or_default() + "\n";
+        let replayed = match &difference.replayed {
+            Some(replayed) => serde_json::to_string_pretty(replayed).unwrap_or_default() + "\n",
+            None => "no response\n".to_string(),
+        };
+        print!("{}", unified_diff(&recorded, &replayed, "recorded", "replayed", 3));
+    }
+    println!(
+        "{} messages replayed, {} responses compared, {} differ",
+        report.replayed,
+        report.compared,
+        report.differences.len()
+    );
+}
+
+/// Send a `tools/call` and print the response, returning whether the call succeeded
+async fn run_tool(server: &FilesystemServer, id: u64, tool: &str, arguments: Value, raw: bool) -> bool {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": tool, "arguments": arguments },
+    });
+    match server.handle(request).await {
+        Some(response) => print_response(&response, raw),
+        None => {
+            eprintln!("No response");
+            false
+        }
+    }
+}
+
+/// Print a response: the JSON, or the text of a tool result, with errors on stderr. Returns
+/// whether it is a success.
+fn print_response(response: &Value, raw: bool) -> bool {
+    let is_error = response.get("error").is_some() || response["result"]["isError"] == json!(true);
+    if raw {
+        println!("{}", serde_json::to_string_pretty(response).unwrap_or_default());
+        return !is_error;
+    }
+    if let Some(error) = response.get("error") {
+        eprintln!("Error {}: {}", error["code"], error["message"].as_str().unwrap_or_default());
+        return false;
+    }
+    for content in response["result"]["content"].as_array().into_iter().flatten() {
+        let text = match content["type"].as_str() {
+            Some("text") => content["text"].as_str().unwrap_or_default().to_string(),
+            Some("resource_link") => content["uri"].as_str().unwrap_or_default().to_string(),
+            _ => content.to_string(),
+        };
+        if is_error {
+            eprintln!("{}", text);
+        } else {
+            println!("{}", text);
+        }
+    }
+    !is_error
+}
+
+/// The arguments of a call: the JSON object, if any, with the `KEY=VALUE` pairs added
+fn build_arguments(tool: &str, pairs: &[String], json: Option<&str>) -> Result<Value, String> {
+    let mut arguments = match json {
+        Some(json) => serde_json::from_str(json).map_err(|e| format!("Invalid JSON arguments: {}", e))?,
+        None => json!({}),
+    };
+    let Some(object) = arguments.as_object_mut() else {
+        return Err("The JSON arguments must be an object".to_string());
+    };
+    let schema = all_tools()
+        .into_iter()
+        .find(|definition| definition.name == tool)
+        .map(|definition| (definition.input_schema)())
+        .unwrap_or_default();
+    for pair in pairs {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("Expected KEY=VALUE, got {}", pair))?;
+        match schema["properties"][key]["type"].as_str() {
+            Some("string") => {
+                object.insert(key.to_string(), json!(value));
+            }
+            // `include=*.rs include=*.toml` builds a list, unless the value is a JSON list
+            Some("array") if !value.starts_with('[') => {
+                let list = object.entry(key).or_insert_with(|| json!([]));
+                match list.as_array_mut() {
+                    Some(list) => list.push(json!(value)),
+                    None => *list = json!([value]),
+                }
+            }
+            _ => {
+                let value = serde_json::from_str(value).unwrap_or_else(|_| json!(value));
+                object.insert(key.to_string(), value);
+            }
+        }
+    }
+    Ok(arguments)
+}
+
+/// Split a line into words like a shell does: quotes group words, and a backslash outside of
+/// single quotes escapes the next character
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some('"') | None, '\\') => {
+                let escaped = chars.next().ok_or("Trailing backslash")?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err("Unclosed quote".to_string());
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_words_like_a_shell() {
+        let words = split_words(r#"path=src content="two words" pattern='a\b' empty="" x\ y"#).unwrap();
+        assert_eq!(words, vec!["path=src", "content=two words", r"pattern=a\b", "empty=", "x y"]);
+        assert!(split_words("content=\"open").is_err());
+        assert_eq!(split_words("   ").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_arguments_follow_the_tool_schema() {
+        let pairs = |pairs: &[&str]| pairs.iter().map(|pair| pair.to_string()).collect::<Vec<_>>();
+        let arguments = build_arguments(
+            "replace_in_files",
+            &pairs(&["pattern=42", "is_regex=true", "include=*.rs", "include=*.toml"]),
+            Some(r#"{"path": "src"}"#),
+        )
+        .unwrap();
+        assert_eq!(
+            arguments,
+            json!({"path": "src", "pattern": "42", "is_regex": true, "include": ["*.rs", "*.toml"]})
+        );
+        let arguments = build_arguments("diff_paths", &pairs(&["context_lines=5", "exclude=[\"a\"]"]), None).unwrap();
+        assert_eq!(arguments, json!({"context_lines": 5, "exclude": ["a"]}));
+
+        assert!(build_arguments("read_file", &pairs(&["file_path"]), None).is_err());
+        assert!(build_arguments("read_file", &[], Some("[1]")).is_err());
+    }
+}
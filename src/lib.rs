@@ -0,0 +1,31 @@
+//! MCP filesystem server, usable as a binary or embedded in another Rust MCP host.
+//!
+//! ```no_run
+//! use rs_filesystem::mcp::config::AccessMode;
+//! use rs_filesystem::FilesystemServer;
+//!
+//! # async fn run() -> Result<(), String> {
+//! let server = FilesystemServer::builder()
+//!     .root("/home/me/project", AccessMode::ReadWrite)
+//!     .root("/etc/myapp", AccessMode::ReadOnly)
+//!     .build()?;
+//! server.serve().await
+//! # }
+//! ```
+//!
+//! The server's settings (roots, policies, extra tools, negotiated client state) are
+//! process-wide, so a process runs a single server. Building another one replaces the settings
+//! of the first.
+pub mod mcp;
+pub mod replay;
+pub mod server;
+
+pub use mcp::tool_registry::schema_for;
+pub use mcp::tool_registry::ToolDefinition;
+pub use mcp::tool_registry::ToolHints;
+pub use mcp::utilities::is_path_allowed;
+pub use mcp::utilities::Access;
+pub use rpc_router;
+pub use server::FilesystemServer;
+pub use server::FilesystemServerBuilder;
+pub use server::Transport;
@@ -1,81 +1,71 @@
-mod mcp;
+mod cli;
 
-use crate::mcp::prompts::prompts_get;
-use crate::mcp::prompts::prompts_list;
-use crate::mcp::resources::resource_read;
-use crate::mcp::resources::resources_list;
-use crate::mcp::resources::{allowed_directories};
-use crate::mcp::tools::register_tools;
-use crate::mcp::tools::tools_list;
-use crate::mcp::types::CancelledNotification;
-use crate::mcp::types::JsonRpcError;
-use crate::mcp::types::JsonRpcResponse;
-use crate::mcp::types::ToolCallRequestParams;
-use crate::mcp::utilities::*;
 use clap::Parser;
-use dirs::data_local_dir;
-use dirs::home_dir;
-use dirs::state_dir;
-use rpc_router::Error;
-use rpc_router::Handler;
-use rpc_router::Request;
-use rpc_router::Router;
-use rpc_router::RouterBuilder;
-use serde_json::json;
-use serde_json::Value;
-use std::env;
-use std::fs::OpenOptions;
-use std::io::Write;
+use clap::Subcommand;
+use rs_filesystem::mcp::http;
+use rs_filesystem::mcp::prompts::prompts_list;
+use rs_filesystem::mcp::resources::resources_list;
+use rs_filesystem::mcp::tools::tools_list;
+use rs_filesystem::mcp::utilities::graceful_shutdown;
+use rs_filesystem::server::DEFAULT_MAX_CONCURRENT_REQUESTS;
+use rs_filesystem::FilesystemServer;
+use rs_filesystem::Transport;
 use std::path::PathBuf;
-use tokio::io::AsyncBufReadExt;
 use tokio::signal;
 
-fn build_rpc_router() -> Router {
-    let builder = RouterBuilder::default()
-        // append resources here
-        .append_dyn("initialize", initialize.into_dyn())
-        .append_dyn("ping", ping.into_dyn())
-        .append_dyn("logging/setLevel", logging_set_level.into_dyn())
-        .append_dyn("roots/list", roots_list.into_dyn())
-        .append_dyn("prompts/list", prompts_list.into_dyn())
-        .append_dyn("prompts/get", prompts_get.into_dyn())
-        .append_dyn("resources/list", resources_list.into_dyn())
-        .append_dyn("resources/read", resource_read.into_dyn())
-        .append_dyn("resources/allowed_directories", allowed_directories.into_dyn());
-    let builder = register_tools(builder);
-    builder.build()
-}
-
-fn get_log_directory() -> PathBuf {
-    if cfg!(target_os = "macos") {
-        // macOS: ~/Library/Logs/Claude
-        home_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("Library/Logs/Claude")
-    } else if cfg!(target_os = "windows") {
-        // Windows: %LOCALAPPDATA%\Claude\logs
-        data_local_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("Claude")
-            .join("logs")
-    } else {
-        // Linux: ~/.local/state/claude/logs
-        state_dir()
-            .unwrap_or_else(|| {
-                home_dir()
-                    .unwrap_or_else(|| PathBuf::from("."))
-                    .join(".local/state")
-            })
-            .join("claude")
-            .join("logs")
-    }
-}
-
 #[tokio::main]
 async fn main() {
     // Parse command-line arguments
-    let args = Args::parse();
-    if !args.mcp {
+    let mut args = Args::parse();
+    let serving = args.mcp || args.http.is_some() || args.socket.is_some();
+    let transport = match (args.http.clone(), args.socket.clone()) {
+        (Some(address), _) => {
+            let token = args.http_token.clone().or_else(|| std::env::var(http::TOKEN_ENV).ok());
+            Transport::Http { address, token }
+        }
+        #[cfg(unix)]
+        (None, Some(path)) => Transport::UnixSocket { path, mode: args.socket_mode },
+        #[cfg(not(unix))]
+        (None, Some(_)) => {
+            eprintln!("--socket is only supported on Unix");
+            std::process::exit(1);
+        }
+        (None, None) => Transport::Stdio,
+    };
+    let mut builder = FilesystemServer::builder()
+        .read_only(args.read_only)
+        .redact_secrets(args.redact_secrets)
+        .transport(transport)
+        .max_concurrent_requests(args.max_concurrent_requests);
+    let command = match args.command.take() {
+        Some(Command::Replay(replay_args)) => {
+            if args.config.is_some() {
+                eprintln!("replay uses the copy of --tree as its only root, so --config doesn't apply");
+                std::process::exit(2);
+            }
+            std::process::exit(cli::replay(builder, replay_args).await);
+        }
+        command => command,
+    };
+    if let Some(config) = args.config.clone() {
+        builder = builder.config_file(config);
+    }
+    let server = match builder.build() {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(command) = command {
+        let exit_code = match command {
+            Command::Call(call_args) => cli::call(&server, call_args).await,
+            Command::Repl => cli::repl(&server).await,
+            Command::Replay(_) => unreachable!("replay runs before the server is built"),
+        };
+        std::process::exit(exit_code);
+    }
+    if !serving {
         display_info(&args).await;
         return;
     }
@@ -108,96 +98,11 @@ async fn main() {
         std::process::exit(0);
     });
 
-    // Process JSON-RPC from MCP client
-    let router = build_rpc_router();
-    let log_path = env::var("MCP_LOG_FILE_PATH").map(PathBuf::from).unwrap_or_else(|_| {
-        get_log_directory().join("rs_filesystem.logs.jsonl")
-    });
-
-    let mut logging_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(&log_path)
-        .unwrap();
-
-    // Spawn a task to read lines from stdin
+    // Process JSON-RPC from MCP clients, over HTTP, a Unix socket or stdio
     let rpc_handle = tokio::spawn(async move {
-        let mut reader = tokio::io::BufReader::new(tokio::io::stdin()).lines();
-
-        while let Ok(Some(line)) = reader.next_line().await {
-            writeln!(logging_file, "{}", line).unwrap();
-            if !line.is_empty() {
-                if let Ok(json_value) = serde_json::from_str::<Value>(&line) {
-                    // Notifications, no response required
-                    if json_value.is_object() && json_value.get("id").is_none() {
-                        if let Some(method) = json_value.get("method") {
-                            if method == "notifications/initialized" {
-                                notifications_initialized();
-                            } else if method == "notifications/cancelled" {
-                                let params_value = json_value.get("params").unwrap();
-                                let cancel_params: CancelledNotification =
-                                    serde_json::from_value(params_value.clone()).unwrap();
-                                notifications_cancelled(cancel_params);
-                            }
-                        }
-                    } else if let Ok(mut rpc_request) = Request::from_value(json_value) {
-                        // Normal JSON-RPC message, and response expected
-                        let id = rpc_request.id.clone();
-                        if rpc_request.method == "tools/call" {
-                            let params = serde_json::from_value::<ToolCallRequestParams>(
-                                rpc_request.params.unwrap(),
-                            )
-                            .unwrap();
-                            rpc_request = Request {
-                                id: id.clone(),
-                                method: params.name,
-                                params: params.arguments,
-                            }
-                        }
-                        match router.call(rpc_request).await {
-                            Ok(call_response) => {
-                                if !call_response.value.is_null() {
-                                    let response =
-                                        JsonRpcResponse::new(id, call_response.value.clone());
-                                    let response_json = serde_json::to_string(&response).unwrap();
-                                    writeln!(logging_file, "{}\n", response_json).unwrap();
-                                    println!("{}", response_json);
-                                }
-                            }
-                            Err(error) => match &error.error {
-                                // Error from JSON-RPC call
-                                Error::Handler(handler) => {
-                                    if let Some(error_value) = handler.get::<Value>() {
-                                        let json_error = json!({
-                                            "jsonrpc": "2.0",
-                                            "error": error_value,
-                                            "id": id
-                                        });
-                                        let response = serde_json::to_string(&json_error).unwrap();
-                                        writeln!(logging_file, "{}\n", response).unwrap();
-                                        println!("{}", response);
-                                    }
-                                }
-                                _ => {
-                                    let json_error = JsonRpcError::new(
-                                        id,
-                                        -1,
-                                        format!(
-                                            "Invalid json-rpc call, error: {}",
-                                            error.error.to_string()
-                                        )
-                                        .as_str(),
-                                    );
-                                    let response = serde_json::to_string(&json_error).unwrap();
-                                    writeln!(logging_file, "{}\n", response).unwrap();
-                                    println!("{}", response);
-                                }
-                            },
-                        }
-                    }
-                }
-            }
+        if let Err(e) = server.serve().await {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
     });
 
@@ -211,6 +116,8 @@ async fn main() {
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// List resources
     #[arg(long, default_value = "false")]
     resources: bool,
@@ -223,6 +130,52 @@ struct Args {
     /// Start MCP server
     #[arg(long, default_value = "false")]
     mcp: bool,
+    /// Path to a TOML config file with allowed roots and permissions
+    /// (defaults to the MCP_RS_FILESYSTEM_CONFIG env variable)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Never modify disk: mutating tools are hidden and refused
+    #[arg(long, default_value = "false", global = true)]
+    read_only: bool,
+    /// Mask secrets in read_file and grep_search output
+    #[arg(long, default_value = "false", global = true)]
+    redact_secrets: bool,
+    /// Serve MCP over Streamable HTTP on this address instead of stdio (port or host:port;
+    /// defaults to localhost)
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = http::DEFAULT_ADDRESS)]
+    http: Option<String>,
+    /// Serve MCP on a Unix domain socket at this path instead of stdio, one session per
+    /// connection
+    #[arg(long, value_name = "PATH", conflicts_with = "http")]
+    socket: Option<PathBuf>,
+    /// Permissions of the socket file, in octal (600 lets only this user connect)
+    #[arg(long, value_name = "MODE", default_value = "600", value_parser = parse_mode)]
+    socket_mode: u32,
+    /// Bearer token HTTP clients must send (defaults to the MCP_RS_FILESYSTEM_HTTP_TOKEN env
+    /// variable)
+    #[arg(long)]
+    http_token: Option<String>,
+    /// Maximum number of requests handled at the same time
+    #[arg(long, default_value_t = DEFAULT_MAX_CONCURRENT_REQUESTS)]
+    max_concurrent_requests: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Call a tool once against the configured roots and print the result
+    Call(cli::CallArgs),
+    /// Call tools interactively
+    Repl,
+    /// Run a session recorded in the server log again, on a copy of a directory tree, and
+    /// report responses that differ from the recorded ones
+    Replay(cli::ReplayArgs),
+}
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("invalid mode {}: expected octal permissions such as 600 or 660", mode))
 }
 
 impl Args {
@@ -265,7 +218,7 @@ async fn display_info(args: &Args) {
         if let Ok(result) = tools_list(None).await {
             println!("tools:");
             for tool in result.tools {
-                println!("    - {}: {}", 
+                println!("    - {}: {}",
                     tool.name,
                     tool.description.unwrap_or_default()
                 );
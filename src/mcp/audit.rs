@@ -0,0 +1,287 @@
+//! Audit log: one JSONL event per tool call with the client, the files touched, bytes and
+//! content hashes, git commits and the outcome. Tools report what they do through the
+//! `record_*` functions, which are no-ops outside of `track`.
+
+use crate::mcp::server_log;
+use crate::mcp::server_log::get_log_directory;
+use crate::mcp::session;
+use crate::mcp::types::Implementation;
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use sha2::Digest;
+use sha2::Sha256;
+use std::cell::RefCell;
+use std::env;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// client that sent `initialize` over stdio, included in every event; sessions keep their own
+static CLIENT_INFO: RwLock<Option<Implementation>> = RwLock::new(None);
+
+tokio::task_local! {
+    static OPERATION: RefCell<Operation>;
+}
+
+/// What a single file went through during a tool call
+#[derive(Debug, Default, Serialize)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    /// the accesses the path was validated for
+    pub access: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_read: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_written: Option<u64>,
+    /// sha256 of the content before the call, or of the content read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256_before: Option<String>,
+    /// sha256 of the content written; absent for deleted files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256_after: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    /// the path this file was moved here from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renamed_from: Option<PathBuf>,
+    /// the access was refused, so the call never touched the file
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub denied: bool,
+}
+
+/// Everything recorded while a tool call runs
+#[derive(Debug, Default)]
+pub struct Operation {
+    pub files: Vec<FileRecord>,
+    pub git_commits: Vec<String>,
+}
+
+impl Operation {
+    fn file(&mut self, path: &Path) -> &mut FileRecord {
+        match self.files.iter().position(|file| file.path == path) {
+            Some(index) => &mut self.files[index],
+            None => {
+                self.files.push(FileRecord {
+                    path: path.to_path_buf(),
+                    ..FileRecord::default()
+                });
+                self.files.last_mut().expect("just pushed")
+            }
+        }
+    }
+}
+
+pub enum Outcome {
+    Success,
+    Error(String),
+}
+
+impl Outcome {
+    /// The outcome of a `CallToolResult` serialized by a tool handler
+    pub fn of_tool_result(value: &Value) -> Outcome {
+        if value.get("isError").and_then(Value::as_bool).unwrap_or(false) {
+            let text = value
+                .pointer("/content/0/text")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            Outcome::Error(text.to_string())
+        } else {
+            Outcome::Success
+        }
+    }
+}
+
+pub fn set_client_info(client_info: Implementation) {
+    match session::current() {
+        Some(session) => session.state().client_info = Some(client_info),
+        None => *CLIENT_INFO.write().unwrap_or_else(|e| e.into_inner()) = Some(client_info),
+    }
+}
+
+fn client_info() -> Option<Implementation> {
+    session::with_state(|state| state.client_info.clone())
+        .unwrap_or_else(|| CLIENT_INFO.read().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+/// set by a server built with its own audit log, which takes precedence over `MCP_AUDIT_LOG_PATH`
+static AUDIT_LOG_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+pub fn set_audit_log_path(path: Option<PathBuf>) {
+    *AUDIT_LOG_PATH.write().unwrap_or_else(|e| e.into_inner()) = path;
+}
+
+/// The path set for the server, `MCP_AUDIT_LOG_PATH`, or `rs_filesystem.audit.jsonl` in the
+/// platform's log directory
+pub fn audit_log_path() -> PathBuf {
+    if let Some(path) = AUDIT_LOG_PATH.read().unwrap_or_else(|e| e.into_inner()).clone() {
+        return path;
+    }
+    env::var("MCP_AUDIT_LOG_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| get_log_directory().join("rs_filesystem.audit.jsonl"))
+}
+
+/// Run a tool call, collecting what it records
+pub async fn track<F: Future>(future: F) -> (F::Output, Operation) {
+    OPERATION
+        .scope(RefCell::new(Operation::default()), async move {
+            let output = future.await;
+            let operation = OPERATION.with(|operation| operation.take());
+            (output, operation)
+        })
+        .await
+}
+
+/// Whether a tool call is being tracked, so callers can skip work such as hashing otherwise
+pub fn is_active() -> bool {
+    OPERATION.try_with(|_| ()).is_ok()
+}
+
+fn with_operation(f: impl FnOnce(&mut Operation)) {
+    let _ = OPERATION.try_with(|operation| f(&mut operation.borrow_mut()));
+}
+
+pub fn record_access(path: &Path, access: &'static str) {
+    with_operation(|operation| {
+        let file = operation.file(path);
+        if !file.access.contains(&access) {
+            file.access.push(access);
+        }
+    });
+}
+
+/// Record an attempted access that was refused
+pub fn record_denied(path: &Path, access: &'static str) {
+    record_access(path, access);
+    with_operation(|operation| operation.file(path).denied = true);
+}
+
+pub fn record_read(path: &Path, content: &[u8]) {
+    with_operation(|operation| {
+        let file = operation.file(path);
+        *file.bytes_read.get_or_insert(0) += content.len() as u64;
+        file.sha256_before.get_or_insert_with(|| sha256(content));
+    });
+}
+
+/// Record a read of content that was streamed rather than held in memory, see `sha256_reader`
+pub fn record_read_digest(path: &Path, bytes: u64, digest: &str) {
+    with_operation(|operation| {
+        let file = operation.file(path);
+        *file.bytes_read.get_or_insert(0) += bytes;
+        file.sha256_before.get_or_insert_with(|| digest.to_string());
+    });
+}
+
+/// Record a write (`after` is `Some`) or deletion (`after` is `None`) of `path`
+pub fn record_write(path: &Path, before: Option<&[u8]>, after: Option<&[u8]>) {
+    with_operation(|operation| {
+        let file = operation.file(path);
+        if let Some(before) = before {
+            file.sha256_before.get_or_insert_with(|| sha256(before));
+        }
+        match after {
+            Some(after) => {
+                *file.bytes_written.get_or_insert(0) += after.len() as u64;
+                file.sha256_after = Some(sha256(after));
+                file.deleted = false;
+            }
+            None => {
+                file.sha256_after = None;
+                file.deleted = true;
+            }
+        }
+    });
+}
+
+/// Record that `source` was moved to `target`: the source is gone and the target holds what it held
+pub fn record_rename(source: &Path, target: &Path) {
+    with_operation(|operation| {
+        operation.file(source).deleted = true;
+        let file = operation.file(target);
+        file.renamed_from = Some(source.to_path_buf());
+        file.deleted = false;
+    });
+}
+
+pub fn record_commit(commit_id: &str) {
+    with_operation(|operation| operation.git_commits.push(commit_id.to_string()));
+}
+
+fn sha256(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Byte count and sha256 of everything `reader` yields, read in chunks
+pub fn sha256_reader(mut reader: impl Read) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    let mut bytes = 0;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok((bytes, format!("{:x}", hasher.finalize())));
+        }
+        hasher.update(&buffer[..read]);
+        bytes += read as u64;
+    }
+}
+
+/// Build the event for a finished tool call
+pub fn event(request_id: &Value, tool: &str, operation: &Operation, outcome: &Outcome) -> Value {
+    let client_info = client_info();
+    let mut event = json!({
+        "timestamp": Utc::now().to_rfc3339(),
+        "client": client_info,
+        "request_id": request_id,
+        "tool": tool,
+        "files": operation.files,
+        "bytes_read": operation.files.iter().filter_map(|file| file.bytes_read).sum::<u64>(),
+        "bytes_written": operation.files.iter().filter_map(|file| file.bytes_written).sum::<u64>(),
+        "git_commits": operation.git_commits,
+    });
+    match outcome {
+        Outcome::Success => event["outcome"] = json!("success"),
+        Outcome::Error(message) => {
+            event["outcome"] = json!("error");
+            event["error"] = json!(message);
+        }
+    }
+    event
+}
+
+fn open_log() -> Result<File, String> {
+    let path = audit_log_path();
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))
+}
+
+/// Whether the audit log can be written, checked before a tool call when auditing is required
+pub fn check_writable() -> Result<(), String> {
+    open_log().map(|_| ()).inspect_err(report_failure)
+}
+
+/// Append the event for a finished tool call to the audit log. A failure is recorded in the
+/// server log and returned.
+pub fn emit(request_id: &Value, tool: &str, operation: &Operation, outcome: &Outcome) -> Result<(), String> {
+    let event = event(request_id, tool, operation, outcome);
+    let mut file = open_log().inspect_err(report_failure)?;
+    writeln!(file, "{}", event)
+        .map_err(|e| format!("Failed to write audit log {}: {}", audit_log_path().display(), e))
+        .inspect_err(report_failure)
+}
+
+fn report_failure(error: &String) {
+    server_log::log_event("audit_log_failed", json!({ "error": error }));
+}
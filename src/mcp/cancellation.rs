@@ -0,0 +1,209 @@
+//! Cancellation of requests in flight (`notifications/cancelled`). Every request runs with a
+//! token: long-running tools check it between steps, child processes are killed when it fires,
+//! and a cancelled request gets no response.
+
+use crate::mcp::progress::Reporter;
+use crate::mcp::session;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::process::Output;
+use std::process::Stdio;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+
+/// tokens of the requests in flight, by session and JSON-encoded id so that `1` and `"1"` stay
+/// distinct, and clients only cancel their own requests
+static IN_FLIGHT: LazyLock<Mutex<HashMap<String, CancellationToken>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+tokio::task_local! {
+    static TOKEN: CancellationToken;
+}
+
+/// A request that can be cancelled. Created as soon as the request is read, so that a
+/// cancellation arriving before its handler starts is not lost.
+pub struct InFlight {
+    key: String,
+    token: CancellationToken,
+}
+
+fn key(id: &Value) -> String {
+    match session::current() {
+        Some(session) => format!("{} {}", session.id, id),
+        None => id.to_string(),
+    }
+}
+
+pub fn track(id: &Value) -> InFlight {
+    let key = key(id);
+    let token = CancellationToken::new();
+    IN_FLIGHT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key.clone(), token.clone());
+    InFlight { key, token }
+}
+
+impl InFlight {
+    /// Run the request's handler, or return `None` if the request is cancelled, in which case
+    /// no response must be sent
+    pub async fn run<F: Future>(self, future: F) -> Option<F::Output> {
+        let token = self.token.clone();
+        let output = TOKEN
+            .scope(token.clone(), async {
+                tokio::select! {
+                    biased;
+                    _ = token.cancelled() => None,
+                    output = future => Some(output),
+                }
+            })
+            .await;
+        output.filter(|_| !token.is_cancelled())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.key);
+    }
+}
+
+/// Cancel request `id`, returning whether it was still in flight
+pub fn cancel(id: &Value) -> bool {
+    match IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner()).get(&key(id)) {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether the current request was cancelled, so a tool should stop
+pub fn is_cancelled() -> bool {
+    TOKEN.try_with(CancellationToken::is_cancelled).unwrap_or(false)
+}
+
+/// Run `command` to completion, killing it if the current request is cancelled. Lines written
+/// to stdout are reported as progress.
+pub async fn output(command: &mut Command) -> io::Result<Output> {
+    command
+        .kill_on_drop(true)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let output = collect_output(command);
+    match TOKEN.try_with(CancellationToken::clone) {
+        Ok(token) => tokio::select! {
+            output = output => output,
+            _ = token.cancelled() => Err(io::Error::new(io::ErrorKind::Interrupted, "request cancelled")),
+        },
+        Err(_) => output.await,
+    }
+}
+
+async fn collect_output(command: &mut Command) -> io::Result<Output> {
+    let mut child = command.spawn()?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let read_stdout = async {
+        let mut reporter = Reporter::for_current_request();
+        let mut output = Vec::new();
+        let mut chunk = [0u8; 8192];
+        let mut lines = 0;
+        loop {
+            let read = stdout.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok::<_, io::Error>(output);
+            }
+            output.extend_from_slice(&chunk[..read]);
+            lines += chunk[..read].iter().filter(|&&byte| byte == b'\n').count() as u64;
+            reporter.report(lines, None, || format!("{} lines of output", lines));
+        }
+    };
+    let read_stderr = async {
+        let mut output = Vec::new();
+        stderr.read_to_end(&mut output).await.map(|_| output)
+    };
+    let (stdout, stderr, status) = tokio::try_join!(read_stdout, read_stderr, child.wait())?;
+    Ok(Output { status, stdout, stderr })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::tools::collect_files;
+    use crate::mcp::tools::replace_in_files;
+    use crate::mcp::tools::ReplaceInFilesRequest;
+    use crate::mcp::types::CallToolResultContent;
+    use crate::mcp::TEST_ENV_LOCK;
+    use serde_json::json;
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_cancelled_request_stops_and_kills_its_process() {
+        let id = json!("cancel-me");
+        let in_flight = track(&id);
+        let request = tokio::spawn(async move {
+            in_flight.run(output(Command::new("sleep").arg("30"))).await
+        });
+        assert!(!cancel(&json!(1)));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(cancel(&id));
+        // the response is suppressed without waiting for `sleep` to finish
+        let result = tokio::time::timeout(Duration::from_secs(5), request).await.unwrap().unwrap();
+        assert!(result.is_none());
+        assert!(!cancel(&id));
+
+        let finished = track(&json!(2)).run(async { is_cancelled() }).await;
+        assert_eq!(finished, Some(false));
+
+        // cancelled before its handler started
+        let in_flight = track(&json!(3));
+        assert!(cancel(&json!(3)));
+        assert_eq!(in_flight.run(async { is_cancelled() }).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_walk_stops_without_modifying_files() {
+        let _env_guard = TEST_ENV_LOCK.lock().await;
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &root);
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src/a.rs"), "old_name\n").unwrap();
+        fs::write(root.join("src/b.rs"), "old_name\n").unwrap();
+
+        // the token of a request that was cancelled while the tool was running
+        let token = CancellationToken::new();
+        token.cancel();
+        let request = ReplaceInFilesRequest {
+            path: root.to_str().unwrap().to_string(),
+            pattern: "old_name".to_string(),
+            replacement: "new_name".to_string(),
+            is_regex: None,
+            case_sensitive: None,
+            include: None,
+            exclude: None,
+            preview: Some(false),
+            commit_message: Some("Rename old_name".to_string()),
+        };
+        let (files, result) = TOKEN
+            .scope(token, async { (collect_files(&root), replace_in_files(request).await.unwrap()) })
+            .await;
+        assert!(files.is_empty());
+        assert!(result.is_error);
+        assert!(matches!(&result.content[0], CallToolResultContent::Text { text } if text.contains("no files were modified")));
+        assert_eq!(fs::read_to_string(root.join("src/a.rs")).unwrap(), "old_name\n");
+        assert_eq!(fs::read_to_string(root.join("src/b.rs")).unwrap(), "old_name\n");
+
+        env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+    }
+}
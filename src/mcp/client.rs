@@ -0,0 +1,101 @@
+//! Requests sent from the server to the client, such as `roots/list`, and routing of the
+//! client's responses back to whoever is waiting for them.
+
+use crate::mcp::output;
+use crate::mcp::session;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// how long to wait for the client to answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// by request id, with the session the request went to
+type PendingRequests = HashMap<String, (Option<String>, oneshot::Sender<Result<Value, Value>>)>;
+
+static PENDING: LazyLock<Mutex<PendingRequests>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Send a request to the client and wait for its result. Must not be awaited by the loop that
+/// reads stdin, since that loop is what delivers the response.
+pub async fn request(method: &str, params: Option<Value>) -> Result<Value, String> {
+    let id = format!("server-{}", NEXT_REQUEST_ID.fetch_add(1, Ordering::SeqCst));
+    let (sender, receiver) = oneshot::channel();
+    let session_id = session::current().map(|session| session.id.clone());
+    PENDING.lock().unwrap_or_else(|e| e.into_inner()).insert(id.clone(), (session_id, sender));
+
+    let mut request = json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+    });
+    if let Some(params) = params {
+        request["params"] = params;
+    }
+    output::send(&request);
+
+    let response = tokio::time::timeout(REQUEST_TIMEOUT, receiver).await;
+    PENDING.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    match response {
+        Ok(Ok(Ok(result))) => Ok(result),
+        Ok(Ok(Err(error))) => Err(format!("{} failed: {}", method, error)),
+        Ok(Err(_)) => Err(format!("{} was abandoned", method)),
+        Err(_) => Err(format!("{} timed out", method)),
+    }
+}
+
+/// If `message` is a response to a request sent with `request` to the current session, deliver
+/// it and return true
+pub fn handle_response(message: &Value) -> bool {
+    if message.get("method").is_some() {
+        return false;
+    }
+    let Some(id) = message.get("id").and_then(Value::as_str) else {
+        return false;
+    };
+    let session_id = session::current().map(|session| session.id.clone());
+    let sender = {
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        match pending.get(id) {
+            Some((sent_to, _)) if *sent_to == session_id => pending.remove(id).map(|(_, sender)| sender),
+            _ => None,
+        }
+    };
+    let Some(sender) = sender else {
+        return false;
+    };
+    let result = match message.get("error") {
+        Some(error) => Err(error.clone()),
+        None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+    };
+    let _ = sender.send(result);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_responses_are_routed_to_pending_requests() {
+        let pending = tokio::spawn(request("roots/list", None));
+        let id = loop {
+            if let Some(id) = PENDING.lock().unwrap().keys().next().cloned() {
+                break id;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert!(!handle_response(&json!({"jsonrpc": "2.0", "id": "unknown", "result": {}})));
+        assert!(!handle_response(&json!({"jsonrpc": "2.0", "id": id, "method": "ping"})));
+        assert!(handle_response(&json!({"jsonrpc": "2.0", "id": id, "result": {"roots": []}})));
+        assert_eq!(pending.await.unwrap(), Ok(json!({"roots": []})));
+        assert!(PENDING.lock().unwrap().is_empty());
+    }
+}
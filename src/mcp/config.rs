@@ -0,0 +1,412 @@
+use crate::mcp::logging;
+use crate::mcp::server_log;
+use crate::mcp::utilities::Access;
+use globset::Glob;
+use globset::GlobSet;
+use globset::GlobSetBuilder;
+use serde::Deserialize;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/// env variable naming the config file, used when `--config` is not given
+pub const CONFIG_ENV_VAR: &str = "MCP_RS_FILESYSTEM_CONFIG";
+
+/// Files holding credentials or keys, protected unless the config file sets `sensitive`
+pub const DEFAULT_SENSITIVE_PATTERNS: &[&str] = &[
+    "**/.env",
+    "**/.env.local",
+    "**/.env.*.local",
+    "**/.env.production",
+    "**/*.pem",
+    "**/*.key",
+    "**/*.p12",
+    "**/*.pfx",
+    "**/*.jks",
+    "**/*.keystore",
+    "**/*.kdbx",
+    "**/id_rsa",
+    "**/id_dsa",
+    "**/id_ecdsa",
+    "**/id_ed25519",
+    "**/.ssh/**",
+    "**/.gnupg/**",
+    "**/.aws/credentials",
+    "**/.azure/**",
+    "**/.config/gcloud/**",
+    "**/.kube/config",
+    "**/.docker/config.json",
+    "**/.netrc",
+    "**/.npmrc",
+    "**/.pypirc",
+    "**/.git-credentials",
+    "**/.htpasswd",
+    "**/credentials.json",
+    "**/service-account*.json",
+];
+
+/// What may be done inside an allowed root
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccessMode {
+    ReadOnly,
+    #[default]
+    ReadWrite,
+    /// files may be created and modified, but not deleted or moved away
+    NoDelete,
+}
+
+impl AccessMode {
+    pub fn permits(self, access: Access) -> bool {
+        match self {
+            AccessMode::ReadWrite => true,
+            AccessMode::ReadOnly => access == Access::Read,
+            AccessMode::NoDelete => access != Access::Delete,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccessMode::ReadOnly => "read-only",
+            AccessMode::ReadWrite => "read-write",
+            AccessMode::NoDelete => "no-delete",
+        }
+    }
+}
+
+/// Contents of the TOML config file, e.g.
+///
+/// ```toml
+/// read_only = false
+/// redact_secrets = true
+/// deny = ["build/**"]
+/// sensitive = ["**/.env", "**/*.pem"]
+/// max_file_size = 10485760
+///
+/// [[roots]]
+/// path = "/home/me/project"
+/// mode = "read-write"
+///
+/// [[roots]]
+/// path = "/etc/myapp"
+/// mode = "read-only"
+/// deny = ["secrets/**"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// refuse every operation that modifies disk
+    pub read_only: bool,
+    /// mask secrets in `read_file` and `grep_search` output
+    pub redact_secrets: bool,
+    pub roots: Vec<RootConfig>,
+    /// globs denied in every root
+    pub deny: Vec<String>,
+    /// globs of files holding secrets, blocked in every root. Defaults to
+    /// `DEFAULT_SENSITIVE_PATTERNS`; an empty list turns the protection off.
+    pub sensitive: Option<Vec<String>>,
+    /// default size limit in bytes for files read or written
+    pub max_file_size: Option<u64>,
+    /// refuse tool calls while the audit log can't be written
+    pub require_audit: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RootConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub mode: AccessMode,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    pub max_file_size: Option<u64>,
+}
+
+/// An allowed root with its deny globs compiled and global defaults applied
+#[derive(Debug, Clone)]
+pub struct RootPolicy {
+    pub path: PathBuf,
+    pub mode: AccessMode,
+    pub deny: GlobSet,
+    pub max_file_size: Option<u64>,
+}
+
+/// The policies in effect, rebuilt whenever the config file changes
+#[derive(Debug)]
+pub struct Policies {
+    pub read_only: bool,
+    pub redact_secrets: bool,
+    pub roots: Vec<RootPolicy>,
+    /// the global deny list, applied to roots that do not come from the config file
+    pub deny: GlobSet,
+    pub sensitive: GlobSet,
+    pub max_file_size: Option<u64>,
+    pub require_audit: bool,
+}
+
+impl Config {
+    pub fn from_toml(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|e| format!("Invalid config: {}", e))
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read config file {}: {}", path.display(), e))?;
+        Config::from_toml(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn policies(&self) -> Result<Policies, String> {
+        let roots = self
+            .roots
+            .iter()
+            .map(|root| {
+                Ok(RootPolicy {
+                    path: root.path.clone(),
+                    mode: root.mode,
+                    deny: build_deny_set(self.deny.iter().chain(root.deny.iter()))?,
+                    max_file_size: root.max_file_size.or(self.max_file_size),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let sensitive = match &self.sensitive {
+            Some(patterns) => build_deny_set(patterns.iter())?,
+            None => build_deny_set(DEFAULT_SENSITIVE_PATTERNS.iter())?,
+        };
+        Ok(Policies {
+            read_only: self.read_only,
+            redact_secrets: self.redact_secrets,
+            roots,
+            deny: build_deny_set(self.deny.iter())?,
+            sensitive,
+            max_file_size: self.max_file_size,
+            require_audit: self.require_audit,
+        })
+    }
+}
+
+fn build_deny_set(patterns: impl Iterator<Item = impl AsRef<str>>) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        let glob = Glob::new(pattern).map_err(|e| format!("Invalid deny pattern {}: {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| format!("Invalid deny patterns: {}", e))
+}
+
+struct LoadedConfig {
+    /// the config file, or `None` for a config set in code, which never changes
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    policies: Arc<Policies>,
+}
+
+static CONFIG: RwLock<Option<LoadedConfig>> = RwLock::new(None);
+
+/// policies used when there is no config file
+static DEFAULT_POLICIES: LazyLock<Arc<Policies>> =
+    LazyLock::new(|| Arc::new(Config::default().policies().expect("default policies are valid")));
+
+/// set by the `--read-only` flag, which cannot be undone by the config file
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// set by the `--redact-secrets` flag
+static REDACT_SECRETS: AtomicBool = AtomicBool::new(false);
+
+/// whether the allowed directories env variable adds roots; off for servers whose roots are set
+/// in code
+static ENV_ROOTS: AtomicBool = AtomicBool::new(true);
+
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::SeqCst);
+}
+
+/// Whether the server must not modify disk, from either `--read-only` or the config file
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::SeqCst) || current().read_only
+}
+
+pub fn set_redact_secrets(redact: bool) {
+    REDACT_SECRETS.store(redact, Ordering::SeqCst);
+}
+
+/// Whether secrets are masked in file content returned to the client
+pub fn redact_secrets() -> bool {
+    REDACT_SECRETS.load(Ordering::SeqCst) || current().redact_secrets
+}
+
+pub fn set_env_roots(env_roots: bool) {
+    ENV_ROOTS.store(env_roots, Ordering::SeqCst);
+}
+
+pub fn env_roots() -> bool {
+    ENV_ROOTS.load(Ordering::SeqCst)
+}
+
+/// Load the config file named by `path`, or by the `MCP_RS_FILESYSTEM_CONFIG` env variable.
+/// Without either, only the allowed directories env variable applies.
+pub fn init(path: Option<PathBuf>) -> Result<(), String> {
+    let path = path.or_else(|| std::env::var(CONFIG_ENV_VAR).ok().map(PathBuf::from));
+    let loaded = match path {
+        Some(path) => {
+            let modified = modified_time(&path);
+            let policies = Config::load(&path)?.policies()?;
+            Some(LoadedConfig {
+                path: Some(path),
+                modified,
+                policies: Arc::new(policies),
+            })
+        }
+        None => None,
+    };
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = loaded;
+    Ok(())
+}
+
+/// Use `config` instead of a config file, for servers configured in code
+pub fn set(config: Config) -> Result<(), String> {
+    let policies = config.policies()?;
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(LoadedConfig {
+        path: None,
+        modified: None,
+        policies: Arc::new(policies),
+    });
+    Ok(())
+}
+
+/// The current policies. The config file is re-read when its modification time changes;
+/// if the new version is invalid, the previous policies stay in effect.
+pub fn current() -> Arc<Policies> {
+    {
+        let guard = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+        match guard.as_ref() {
+            None => return DEFAULT_POLICIES.clone(),
+            Some(loaded) if loaded.path.as_deref().and_then(modified_time) == loaded.modified => {
+                return loaded.policies.clone()
+            }
+            Some(_) => {}
+        }
+    }
+
+    let mut guard = CONFIG.write().unwrap_or_else(|e| e.into_inner());
+    let Some(loaded) = guard.as_mut() else {
+        return DEFAULT_POLICIES.clone();
+    };
+    let Some(path) = loaded.path.clone() else {
+        return loaded.policies.clone();
+    };
+    let modified = modified_time(&path);
+    if modified == loaded.modified {
+        return loaded.policies.clone();
+    }
+    // the new modification time is kept even if the file is invalid, so it is reported once
+    loaded.modified = modified;
+    let error = match Config::load(&path).and_then(|config| config.policies()) {
+        Ok(policies) => {
+            loaded.policies = Arc::new(policies);
+            return loaded.policies.clone();
+        }
+        Err(e) => e,
+    };
+    let policies = loaded.policies.clone();
+    drop(guard);
+    server_log::log_event("config_reload_failed", json!({ "path": path, "error": error }));
+    logging::warning("config", format!("Keeping previous configuration: {}", error));
+    policies
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::from_toml(
+            r#"
+read_only = true
+deny = ["**/.env"]
+max_file_size = 100
+
+[[roots]]
+path = "/srv/app"
+mode = "read-only"
+deny = ["**/*.pem"]
+
+[[roots]]
+path = "/home/me/project"
+max_file_size = 5
+"#,
+        )
+        .unwrap();
+        let policies = config.policies().unwrap();
+        assert!(policies.read_only);
+        assert_eq!(policies.roots.len(), 2);
+        assert_eq!(policies.roots[0].mode, AccessMode::ReadOnly);
+        assert!(policies.roots[0].deny.is_match("config/.env"));
+        assert!(policies.roots[0].deny.is_match("certs/server.pem"));
+        assert_eq!(policies.roots[0].max_file_size, Some(100));
+        assert_eq!(policies.roots[1].mode, AccessMode::ReadWrite);
+        assert!(!policies.roots[1].deny.is_match("certs/server.pem"));
+        assert_eq!(policies.roots[1].max_file_size, Some(5));
+    }
+
+    #[test]
+    fn test_access_modes() {
+        assert!(AccessMode::ReadOnly.permits(Access::Read));
+        assert!(!AccessMode::ReadOnly.permits(Access::Write));
+        assert!(AccessMode::NoDelete.permits(Access::Write));
+        assert!(!AccessMode::NoDelete.permits(Access::Delete));
+        assert!(AccessMode::ReadWrite.permits(Access::Delete));
+        assert!(Config::from_toml("[[roots]]\npath = \"/x\"\nmode = \"bogus\"").is_err());
+    }
+
+    #[test]
+    fn test_sensitive_patterns() {
+        let defaults = Config::default().policies().unwrap();
+        assert!(defaults.sensitive.is_match(".env"));
+        assert!(defaults.sensitive.is_match("deploy/certs/server.pem"));
+        assert!(defaults.sensitive.is_match("/home/me/.ssh/config"));
+        assert!(!defaults.sensitive.is_match(".env.example"));
+        assert!(!defaults.redact_secrets);
+
+        let custom = Config::from_toml("redact_secrets = true\nsensitive = [\"**/*.secret\"]").unwrap().policies().unwrap();
+        assert!(custom.sensitive.is_match("db.secret"));
+        assert!(!custom.sensitive.is_match(".env"));
+        assert!(custom.redact_secrets);
+        let disabled = Config::from_toml("sensitive = []").unwrap().policies().unwrap();
+        assert!(!disabled.sensitive.is_match(".env"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_reload_keeps_policies_and_is_reported_once() {
+        let _env_guard = crate::mcp::TEST_ENV_LOCK.lock().await;
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("server.logs.jsonl");
+        server_log::set_log_path(Some(log_path.clone()));
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "read_only = true\n").unwrap();
+        init(Some(config_path.clone())).unwrap();
+        assert!(current().read_only);
+
+        fs::write(&config_path, "read_only = \"maybe\"\n").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(10);
+        fs::File::options().write(true).open(&config_path).unwrap().set_modified(later).unwrap();
+        assert!(current().read_only);
+        assert!(current().read_only);
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert_eq!(log.matches("config_reload_failed").count(), 1);
+
+        init(None).unwrap();
+        server_log::set_log_path(None);
+    }
+}
@@ -0,0 +1,70 @@
+//! Per-file locks held by tools that modify files, so that concurrent requests editing the same
+//! file are applied one after the other instead of overwriting each other's changes
+
+use crate::mcp::paths::resolve_path;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use tokio::sync::Mutex as FileMutex;
+use tokio::sync::OwnedMutexGuard;
+
+static LOCKS: LazyLock<Mutex<HashMap<PathBuf, Arc<FileMutex<()>>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Locks on a set of files, released on drop
+pub struct FileLocks {
+    guards: Vec<OwnedMutexGuard<()>>,
+}
+
+/// Lock `paths`. Paths are resolved first so that every alias of a file shares its lock, and
+/// locked in sorted order so that requests locking overlapping sets can't deadlock.
+pub async fn lock(paths: impl IntoIterator<Item = PathBuf>) -> FileLocks {
+    let mut paths: Vec<PathBuf> = paths
+        .into_iter()
+        .map(|path| resolve_path(&path).unwrap_or(path))
+        .collect();
+    paths.sort();
+    paths.dedup();
+    let mut guards = Vec::with_capacity(paths.len());
+    for path in paths {
+        let lock = LOCKS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(path)
+            .or_default()
+            .clone();
+        guards.push(lock.lock_owned().await);
+    }
+    FileLocks { guards }
+}
+
+impl Drop for FileLocks {
+    fn drop(&mut self) {
+        self.guards.clear();
+        // forget locks nobody holds or waits for
+        LOCKS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, lock| Arc::strong_count(lock) > 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_locks_serialize_access_to_a_file() {
+        let path = PathBuf::from("/locked/file.txt");
+        let held = lock([path.clone(), PathBuf::from("/locked/other.txt")]).await;
+        let waiting = tokio::spawn(lock([path.clone()]));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        drop(held);
+        let relocked = tokio::time::timeout(Duration::from_secs(5), waiting).await.unwrap().unwrap();
+        drop(relocked);
+        assert!(!LOCKS.lock().unwrap().keys().any(|key| key.starts_with("/locked")));
+    }
+}
@@ -0,0 +1,390 @@
+//! MCP Streamable HTTP transport. Clients POST JSON-RPC messages to `/mcp` and get the response
+//! in the reply, and GET `/mcp` to open an SSE stream for notifications and server requests.
+//! `initialize` starts a session, whose id the client sends back in the `Mcp-Session-Id` header;
+//! DELETE ends it, and so does a long enough time without requests or an open stream.
+
+use crate::mcp::output;
+use crate::mcp::protocol;
+use crate::mcp::rpc;
+use crate::mcp::rpc::Dispatcher;
+use crate::mcp::server_log;
+use crate::mcp::session;
+use crate::mcp::session::Session;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::post;
+use axum::Json;
+use serde_json::json;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tokio_util::sync::WaitForCancellationFutureOwned;
+
+/// where `--http` listens without an address: only local clients can connect
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8765";
+pub const TOKEN_ENV: &str = "MCP_RS_FILESYSTEM_HTTP_TOKEN";
+
+const ENDPOINT: &str = "/mcp";
+const SESSION_HEADER: &str = "mcp-session-id";
+const PROTOCOL_VERSION_HEADER: &str = "mcp-protocol-version";
+
+/// sessions whose client sent nothing for this long, without a stream open, are ended
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// why a request is refused, sent back as a JSON error
+type Rejection = (StatusCode, &'static str);
+
+#[derive(Clone)]
+struct Server {
+    dispatcher: Dispatcher,
+    /// bearer token clients must send, if any
+    token: Option<Arc<str>>,
+    /// the address the server listens on
+    address: SocketAddr,
+}
+
+/// Serve MCP over HTTP on `address`, a port or host:port, until the process exits
+pub async fn serve(address: &str, token: Option<String>, dispatcher: Dispatcher) -> Result<(), String> {
+    let address = parse_address(address)?;
+    if token.is_none() && !address.ip().is_loopback() {
+        return Err(format!(
+            "Refusing to serve on {} without --http-token: anyone who can reach it could use the tools",
+            address
+        ));
+    }
+    let listener = TcpListener::bind(address)
+        .await
+        .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    output::sessions_only();
+    tokio::spawn(sweep_idle_sessions());
+    let server = Server {
+        dispatcher,
+        token: token.map(Arc::from),
+        address,
+    };
+    let app = axum::Router::new()
+        .route(ENDPOINT, post(handle_post).get(handle_get).delete(handle_delete))
+        .with_state(server);
+    axum::serve(listener, app)
+        .await
+        .map_err(|e| format!("HTTP server failed: {}", e))
+}
+
+/// End the sessions of clients that went away without a DELETE, with their subscriptions
+async fn sweep_idle_sessions() {
+    let mut interval = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        for id in session::remove_idle(SESSION_IDLE_TIMEOUT) {
+            server_log::log_event("session_expired", json!({ "session": id }));
+        }
+    }
+}
+
+/// A bare port means localhost
+fn parse_address(address: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = address.parse::<u16>() {
+        return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port));
+    }
+    address
+        .parse()
+        .map_err(|_| format!("Invalid HTTP address: {} (expected a port or host:port)", address))
+}
+
+/// Handle one message or batch. Without a session id it must be `initialize`, which starts one.
+async fn handle_post(State(server): State<Server>, headers: HeaderMap, body: Bytes) -> Response {
+    if let Err((status, message)) = check_request(&server, &headers) {
+        return error(status, message);
+    }
+    let Ok(message) = serde_json::from_slice::<Value>(&body) else {
+        return (StatusCode::BAD_REQUEST, Json(rpc::parse_error())).into_response();
+    };
+    server_log::log_received(&message);
+
+    let (session, created) = match session_of(&headers) {
+        Ok(Some(session)) => (session, false),
+        Ok(None) if message.get("method").and_then(Value::as_str) == Some("initialize") => (session::create(), true),
+        Ok(None) => return error(StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header; call initialize first"),
+        Err((status, message)) => return error(status, message),
+    };
+    let dispatcher = server.dispatcher.clone();
+    let response = session::scope(Some(session.clone()), async move { dispatcher.dispatch(message).await }).await;
+    let Some(response) = response else {
+        return StatusCode::ACCEPTED.into_response();
+    };
+    server_log::log_message(&response);
+    if created && response.get("error").is_some() {
+        // initialize failed, so there is no session to continue
+        session::remove(&session.id);
+        return Json(response).into_response();
+    }
+    ([(SESSION_HEADER, session.id.clone())], Json(response)).into_response()
+}
+
+/// Open the SSE stream of a session. A session has at most one stream; messages sent while it
+/// is closed are delivered when the client reconnects.
+async fn handle_get(State(server): State<Server>, headers: HeaderMap) -> Response {
+    if let Err((status, message)) = check_request(&server, &headers) {
+        return error(status, message);
+    }
+    let accepts_events = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+    if !accepts_events {
+        return error(StatusCode::NOT_ACCEPTABLE, "GET opens an event stream: accept text/event-stream");
+    }
+    let session = match session_of(&headers) {
+        Ok(Some(session)) => session,
+        Ok(None) => return error(StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header"),
+        Err((status, message)) => return error(status, message),
+    };
+    let Some(receiver) = session.take_receiver() else {
+        return error(StatusCode::CONFLICT, "The session already has an open event stream");
+    };
+    let events = SessionEvents {
+        ended: Box::pin(session.ended.clone().cancelled_owned()),
+        session,
+        receiver: Some(receiver),
+    };
+    Sse::new(events).keep_alive(KeepAlive::default()).into_response()
+}
+
+async fn handle_delete(State(server): State<Server>, headers: HeaderMap) -> Response {
+    if let Err((status, message)) = check_request(&server, &headers) {
+        return error(status, message);
+    }
+    match session_of(&headers) {
+        Ok(Some(session)) => {
+            session::remove(&session.id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(None) => error(StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header"),
+        Err((status, message)) => error(status, message),
+    }
+}
+
+/// Refuse requests without the bearer token, addressed to a host name other than the server's
+/// own, from web pages of other sites, or for a protocol revision the server doesn't speak
+fn check_request(server: &Server, headers: &HeaderMap) -> Result<(), Rejection> {
+    if let Some(token) = &server.token {
+        let sent = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !sent.is_some_and(|sent| constant_time_eq(sent.as_bytes(), token.as_bytes())) {
+            return Err((StatusCode::UNAUTHORIZED, "Missing or invalid bearer token"));
+        }
+    } else {
+        // After DNS rebinding, a page of another site reaches the server under that site's name
+        let host = headers.get(header::HOST).and_then(|host| host.to_str().ok());
+        if !host.is_some_and(|host| is_allowed_host(host, server.address)) {
+            return Err((StatusCode::FORBIDDEN, "Host not allowed"));
+        }
+    }
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        if !origin.to_str().is_ok_and(|origin| is_allowed_origin(origin, server.address)) {
+            return Err((StatusCode::FORBIDDEN, "Origin not allowed"));
+        }
+    }
+    if let Some(version) = headers.get(PROTOCOL_VERSION_HEADER) {
+        if !version.to_str().is_ok_and(|version| protocol::SUPPORTED_VERSIONS.contains(&version)) {
+            return Err((StatusCode::BAD_REQUEST, "Unsupported MCP-Protocol-Version"));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `host`, without its port, is localhost, a loopback address or the address the
+/// server is bound to
+fn is_local_name(host: &str, address: SocketAddr) -> bool {
+    if host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    host.trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+        .is_ok_and(|ip| ip.is_loopback() || (!ip.is_unspecified() && ip == address.ip()))
+}
+
+/// The `Host` header must name the server by a loopback name or its own address, so that a
+/// site whose name was rebound to this machine cannot talk to it
+fn is_allowed_host(host: &str, address: SocketAddr) -> bool {
+    let Ok(authority) = host.parse::<axum::http::uri::Authority>() else {
+        return false;
+    };
+    is_local_name(authority.host(), address)
+}
+
+/// Pages served from localhost or from the server's own address may connect, which stops other
+/// sites from reaching a local server through the browser
+fn is_allowed_origin(origin: &str, address: SocketAddr) -> bool {
+    let Ok(origin) = url::Url::parse(origin) else {
+        return false;
+    };
+    origin.host_str().is_some_and(|host| is_local_name(host, address))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (x, y)| difference | (x ^ y)) == 0
+}
+
+/// The session named by the `Mcp-Session-Id` header, `None` without the header, or a 404 for
+/// an unknown or ended session
+fn session_of(headers: &HeaderMap) -> Result<Option<Arc<Session>>, Rejection> {
+    let Some(id) = headers.get(SESSION_HEADER) else {
+        return Ok(None);
+    };
+    let session = id
+        .to_str()
+        .ok()
+        .and_then(session::get)
+        .ok_or((StatusCode::NOT_FOUND, "Unknown or expired session"))?;
+    session.touch();
+    Ok(Some(session))
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// The outbound messages of a session as server-sent events, until the session ends. The queue
+/// goes back to the session when the client disconnects.
+struct SessionEvents {
+    session: Arc<Session>,
+    receiver: Option<mpsc::Receiver<Value>>,
+    ended: Pin<Box<WaitForCancellationFutureOwned>>,
+}
+
+impl Stream for SessionEvents {
+    type Item = Result<Event, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.ended.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(None);
+        }
+        let Some(receiver) = self.receiver.as_mut() else {
+            return Poll::Ready(None);
+        };
+        receiver
+            .poll_recv(cx)
+            .map(|message| message.map(|message| Ok(Event::default().event("message").data(message.to_string()))))
+    }
+}
+
+impl Drop for SessionEvents {
+    fn drop(&mut self) {
+        if let Some(receiver) = self.receiver.take() {
+            self.session.return_receiver(receiver);
+        }
+        // the idle timeout starts when the client disconnects
+        self.session.touch();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addresses_and_origins() {
+        assert_eq!(parse_address("8080").unwrap(), "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(parse_address("0.0.0.0:9000").unwrap(), "0.0.0.0:9000".parse().unwrap());
+        assert!(parse_address("localhost").is_err());
+
+        let local: SocketAddr = "127.0.0.1:8765".parse().unwrap();
+        let lan: SocketAddr = "192.168.1.5:8765".parse().unwrap();
+        assert!(is_allowed_origin("http://localhost:3000", local));
+        assert!(is_allowed_origin("http://127.0.0.1:5173", local));
+        assert!(is_allowed_origin("http://[::1]:8000", local));
+        assert!(is_allowed_origin("http://192.168.1.5:8765", lan));
+        assert!(!is_allowed_origin("http://192.168.1.5:8765", local));
+        assert!(!is_allowed_origin("https://evil.example", local));
+        assert!(!is_allowed_origin("null", local));
+
+        // A rebound name is refused even when Origin and Host agree
+        assert!(is_allowed_host("127.0.0.1:8765", local));
+        assert!(is_allowed_host("localhost:8765", local));
+        assert!(is_allowed_host("[::1]:8765", local));
+        assert!(is_allowed_host("192.168.1.5:8765", lan));
+        assert!(!is_allowed_host("evil.example:8765", local));
+        assert!(!is_allowed_host("0.0.0.0:8765", "0.0.0.0:8765".parse().unwrap()));
+        assert!(!is_allowed_host("", local));
+
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT") && !constant_time_eq(b"secret", b"secrets"));
+    }
+
+    #[tokio::test]
+    async fn test_refused_requests_and_binds() {
+        let dispatcher = || Dispatcher::new(rpc_router::RouterBuilder::default().build(), 1);
+        let err = serve("0.0.0.0:0", None, dispatcher()).await.unwrap_err();
+        assert!(err.contains("without --http-token"), "{}", err);
+        assert!(serve("localhost:80", None, dispatcher()).await.unwrap_err().contains("Invalid HTTP address"));
+
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(header::HeaderName::from_static(name), value.parse().unwrap());
+            }
+            headers
+        };
+        let status = |server: &Server, headers: &HeaderMap| check_request(server, headers).map_err(|(status, _)| status);
+        let local = Server {
+            dispatcher: dispatcher(),
+            token: None,
+            address: "127.0.0.1:8765".parse().unwrap(),
+        };
+        let host = ("host", "127.0.0.1:8765");
+        assert_eq!(status(&local, &headers(&[host])), Ok(()));
+        assert_eq!(status(&local, &headers(&[])), Err(StatusCode::FORBIDDEN));
+        assert_eq!(status(&local, &headers(&[("host", "evil.example:8765")])), Err(StatusCode::FORBIDDEN));
+        assert_eq!(
+            status(&local, &headers(&[host, ("origin", "https://evil.example")])),
+            Err(StatusCode::FORBIDDEN)
+        );
+        let version = (PROTOCOL_VERSION_HEADER, "1999-01-01");
+        assert_eq!(status(&local, &headers(&[host, version])), Err(StatusCode::BAD_REQUEST));
+
+        // with a token, the Host header may be any name but the token must match
+        let shared = Server {
+            token: Some(Arc::from("secret")),
+            address: "0.0.0.0:8765".parse().unwrap(),
+            ..local
+        };
+        let host = ("host", "build-box:8765");
+        assert_eq!(status(&shared, &headers(&[host])), Err(StatusCode::UNAUTHORIZED));
+        assert_eq!(
+            status(&shared, &headers(&[host, ("authorization", "Bearer secreT")])),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            status(&shared, &headers(&[host, ("authorization", "secret")])),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(status(&shared, &headers(&[host, ("authorization", "Bearer secret")])), Ok(()));
+
+        let unknown = headers(&[(SESSION_HEADER, "no-such-session")]);
+        assert_eq!(session_of(&unknown).map(|_| ()).unwrap_err().0, StatusCode::NOT_FOUND);
+        assert!(session_of(&headers(&[])).unwrap().is_none());
+    }
+}
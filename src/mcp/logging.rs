@@ -0,0 +1,114 @@
+//! MCP logging: `logging/setLevel` and `notifications/message`. Messages below the level the
+//! client asked for are dropped; the logger is the tool or subsystem the message comes from.
+
+use crate::mcp::audit::Operation;
+use crate::mcp::audit::Outcome;
+use crate::mcp::session;
+use crate::mcp::types::*;
+use crate::mcp::utilities::notify;
+use rpc_router::HandlerResult;
+use rpc_router::IntoHandlerError;
+use serde_json::json;
+use serde_json::Value;
+use std::sync::RwLock;
+
+/// least severe level sent to the client, until it calls `logging/setLevel`
+const DEFAULT_LEVEL: LoggingLevel = LoggingLevel::Info;
+
+/// the level of the stdio client; sessions keep their own
+static LEVEL: RwLock<LoggingLevel> = RwLock::new(DEFAULT_LEVEL);
+
+pub fn level() -> LoggingLevel {
+    session::with_state(|state| state.log_level.unwrap_or(DEFAULT_LEVEL))
+        .unwrap_or_else(|| *LEVEL.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+pub fn set_level(level: LoggingLevel) {
+    if session::with_state(|state| state.log_level = Some(level)).is_none() {
+        *LEVEL.write().unwrap_or_else(|e| e.into_inner()) = level;
+    }
+}
+
+pub async fn logging_set_level(request: SetLevelRequest) -> HandlerResult<LoggingResponse> {
+    let level = serde_json::from_value(json!(request.level)).map_err(|_| {
+        json!({
+            "code": ErrorCode::InvalidParams as i32,
+            "message": format!("Invalid logging level: {}", request.level),
+        })
+        .into_handler_error()
+    })?;
+    set_level(level);
+    Ok(LoggingResponse {})
+}
+
+/// The notification for a message, or `None` if it is below the current level
+fn message(level: LoggingLevel, logger: &str, data: Value) -> Option<LoggingMessageNotification> {
+    (level >= self::level()).then(|| LoggingMessageNotification {
+        level,
+        logger: Some(logger.to_string()),
+        data,
+    })
+}
+
+/// Send a log message to the client
+pub fn log(level: LoggingLevel, logger: &str, data: impl Into<Value>) {
+    if let Some(message) = message(level, logger, data.into()) {
+        notify("notifications/message", serde_json::to_value(message).ok());
+    }
+}
+
+pub fn debug(logger: &str, data: impl Into<Value>) {
+    log(LoggingLevel::Debug, logger, data);
+}
+
+pub fn info(logger: &str, data: impl Into<Value>) {
+    log(LoggingLevel::Info, logger, data);
+}
+
+pub fn warning(logger: &str, data: impl Into<Value>) {
+    log(LoggingLevel::Warning, logger, data);
+}
+
+pub fn error(logger: &str, data: impl Into<Value>) {
+    log(LoggingLevel::Error, logger, data);
+}
+
+/// Log the end of a tool call: failures as errors, changes to files as info, anything else as
+/// debug
+pub fn tool_call(tool: &str, operation: &Operation, outcome: &Outcome) {
+    if let Outcome::Error(message) = outcome {
+        error(tool, message.as_str());
+        return;
+    }
+    let modified: Vec<_> = operation
+        .files
+        .iter()
+        .filter(|file| file.bytes_written.is_some() || file.deleted || file.renamed_from.is_some())
+        .map(|file| &file.path)
+        .collect();
+    if modified.is_empty() {
+        debug(tool, json!({ "message": "completed", "files": operation.files.len() }));
+    } else {
+        info(tool, json!({ "message": "modified files", "files": modified }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_messages_below_the_level_are_dropped() {
+        let set = |level: &str| logging_set_level(SetLevelRequest { level: level.to_string() });
+        set("warning").await.unwrap();
+        assert!(message(LoggingLevel::Info, "grep_search", json!("hidden")).is_none());
+        let sent = message(LoggingLevel::Error, "grep_search", json!("shown")).unwrap();
+        assert_eq!(
+            serde_json::to_value(sent).unwrap(),
+            json!({"level": "error", "logger": "grep_search", "data": "shown"})
+        );
+        assert!(set("verbose").await.is_err());
+        assert_eq!(level(), LoggingLevel::Warning);
+        set("info").await.unwrap();
+    }
+}
@@ -1,10 +1,36 @@
+pub mod audit;
+pub mod cancellation;
+pub mod client;
+pub mod config;
+pub mod file_locks;
+pub mod http;
+pub mod logging;
+pub mod output;
+pub mod patch;
+pub mod paths;
+pub mod progress;
 pub mod prompts;
+pub mod protocol;
+pub mod redact;
 pub mod resources;
+pub mod roots;
+pub mod rpc;
+pub mod server_log;
+pub mod session;
+pub mod subscriptions;
+pub mod tool_registry;
 pub mod tools;
 pub mod types;
+#[cfg(unix)]
+pub mod unix_socket;
 pub mod utilities;
+pub mod workspace;
 
 const JSONRPC_VERSION: &str = "2.0";
-const PROTOCOL_VERSION: &str = "2024-11-05";
 const SERVER_NAME: &str = "rs_filesystem";
+const SERVER_TITLE: &str = "Rust Filesystem";
 const SERVER_VERSION: &str = "0.1.0";
+
+/// Tests that change the allowed directories mutate process-wide state, so they hold this lock.
+#[cfg(test)]
+pub(crate) static TEST_ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
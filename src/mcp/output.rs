@@ -0,0 +1,83 @@
+//! Messages from the server to the client. Requests are handled concurrently, so everything
+//! written to stdout goes through a single writer task and JSON lines never interleave. Messages
+//! sent on behalf of an HTTP session go to that session instead.
+
+use crate::mcp::session;
+use serde_json::Value;
+use std::sync::OnceLock;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// the stdout writer, or `None` when the server only talks to HTTP sessions
+static WRITER: OnceLock<Option<mpsc::UnboundedSender<Output>>> = OnceLock::new();
+
+enum Output {
+    Line(String),
+    /// answered once everything sent before it has been written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Start the task that writes messages to stdout, one per line, in the order they were sent
+pub fn start_stdout_writer() -> JoinHandle<()> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let _ = WRITER.set(Some(sender));
+    tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(output) = receiver.recv().await {
+            match output {
+                Output::Line(line) => {
+                    let written = async {
+                        stdout.write_all(line.as_bytes()).await?;
+                        stdout.write_all(b"\n").await?;
+                        stdout.flush().await
+                    };
+                    if written.await.is_err() {
+                        break;
+                    }
+                }
+                Output::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    })
+}
+
+/// Don't write to stdout, for the HTTP transport: messages outside of a session go to all
+/// sessions
+pub fn sessions_only() {
+    let _ = WRITER.set(None);
+}
+
+/// Send a response, request or notification to the client: to the current session if there is
+/// one, otherwise to stdout and every session. Without a writer (CLI mode and tests) the message
+/// is printed directly.
+pub fn send(message: &Value) {
+    if let Some(session) = session::current() {
+        session.send(message);
+        return;
+    }
+    session::broadcast(message);
+    let line = message.to_string();
+    match WRITER.get() {
+        // the writer only stops once stdout is closed, and then nobody is listening
+        Some(Some(writer)) => {
+            let _ = writer.send(Output::Line(line));
+        }
+        Some(None) => {}
+        None => println!("{}", line),
+    }
+}
+
+/// Wait until every message sent so far has been written
+pub async fn flush() {
+    let Some(Some(writer)) = WRITER.get() else {
+        return;
+    };
+    let (done, written) = oneshot::channel();
+    if writer.send(Output::Flush(done)).is_ok() {
+        let _ = written.await;
+    }
+}
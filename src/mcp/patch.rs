@@ -0,0 +1,667 @@
+//! Parsing, application and generation of unified diffs, as produced by `diff -u` or `git diff`.
+
+use similar::capture_diff_slices;
+use similar::group_diff_ops;
+use similar::Algorithm;
+use similar::DiffTag;
+use similar::TextDiff;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<HunkLine>,
+    /// the old side of the hunk ends without a trailing newline
+    pub old_no_newline: bool,
+    /// the new side of the hunk ends without a trailing newline
+    pub new_no_newline: bool,
+}
+
+impl Hunk {
+    pub fn header(&self) -> String {
+        format!(
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_len, self.new_start, self.new_len
+        )
+    }
+
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(s) | HunkLine::Remove(s) => Some(s.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(s) | HunkLine::Add(s) => Some(s.as_str()),
+                HunkLine::Remove(_) => None,
+            })
+            .collect()
+    }
+
+    fn leading_context(&self) -> usize {
+        self.lines
+            .iter()
+            .take_while(|line| matches!(line, HunkLine::Context(_)))
+            .count()
+    }
+
+    fn trailing_context(&self) -> usize {
+        self.lines
+            .iter()
+            .rev()
+            .take_while(|line| matches!(line, HunkLine::Context(_)))
+            .count()
+    }
+}
+
+/// All hunks for a single file. A `None` path means `/dev/null`, i.e. the file is created or deleted.
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FilePatch {
+    pub fn is_new_file(&self) -> bool {
+        self.old_path.is_none()
+    }
+
+    pub fn is_deletion(&self) -> bool {
+        self.new_path.is_none()
+    }
+
+    pub fn is_rename(&self) -> bool {
+        matches!((&self.old_path, &self.new_path), (Some(old), Some(new)) if old != new)
+    }
+
+    /// the path used to refer to this file in messages
+    pub fn display_path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+/// A hunk that could not be applied, with enough detail for the caller to fix the patch.
+#[derive(Debug, Clone)]
+pub struct HunkRejection {
+    pub hunk_number: usize,
+    pub header: String,
+    pub reason: String,
+}
+
+impl fmt::Display for HunkRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "hunk #{} {} rejected: {}", self.hunk_number, self.header, self.reason)
+    }
+}
+
+/// Parse a (possibly multi-file) unified diff.
+pub fn parse_unified_diff(text: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut patches = Vec::new();
+    let mut i = 0;
+
+    // Header state for the file currently being described
+    let mut git_old: Option<Option<String>> = None;
+    let mut git_new: Option<Option<String>> = None;
+    let mut in_git_header = false;
+
+    while i < lines.len() {
+        let line = lines[i];
+
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            flush_git_only_patch(&mut patches, &mut git_old, &mut git_new, in_git_header);
+            in_git_header = true;
+            if let Some((old, new)) = split_git_diff_paths(rest) {
+                git_old = Some(Some(strip_prefix_component(&old)));
+                git_new = Some(Some(strip_prefix_component(&new)));
+            }
+            i += 1;
+            continue;
+        }
+
+        if in_git_header {
+            if let Some(path) = line.strip_prefix("rename from ") {
+                git_old = Some(Some(path.to_string()));
+                i += 1;
+                continue;
+            }
+            if let Some(path) = line.strip_prefix("rename to ") {
+                git_new = Some(Some(path.to_string()));
+                i += 1;
+                continue;
+            }
+            if line.starts_with("new file mode") {
+                git_old = Some(None);
+                i += 1;
+                continue;
+            }
+            if line.starts_with("deleted file mode") {
+                git_new = Some(None);
+                i += 1;
+                continue;
+            }
+            if line.starts_with("Binary files ") || line.starts_with("GIT binary patch") {
+                return Err(format!("Binary patches are not supported (line {})", i + 1));
+            }
+        }
+
+        if line.starts_with("--- ") && i + 1 < lines.len() && lines[i + 1].starts_with("+++ ") {
+            let old_path = parse_header_path(&line[4..]);
+            let new_path = parse_header_path(&lines[i + 1][4..]);
+            i += 2;
+
+            let mut hunks = Vec::new();
+            while i < lines.len() && lines[i].starts_with("@@") {
+                let (hunk, next) = parse_hunk(&lines, i)?;
+                hunks.push(hunk);
+                i = next;
+            }
+            if hunks.is_empty() {
+                return Err(format!(
+                    "No hunks found for {}",
+                    new_path.as_deref().or(old_path.as_deref()).unwrap_or("/dev/null")
+                ));
+            }
+
+            // Prefer the git header for renames, since ---/+++ lines may be absent or ambiguous
+            let old_path = match (&git_old, &old_path) {
+                (Some(Some(git)), Some(_)) => Some(git.clone()),
+                _ => old_path,
+            };
+            let new_path = match (&git_new, &new_path) {
+                (Some(Some(git)), Some(_)) => Some(git.clone()),
+                _ => new_path,
+            };
+            if old_path.is_none() && new_path.is_none() {
+                return Err(format!("Both sides of the diff are /dev/null (line {})", i));
+            }
+            patches.push(FilePatch { old_path, new_path, hunks });
+            git_old = None;
+            git_new = None;
+            in_git_header = false;
+            continue;
+        }
+
+        i += 1;
+    }
+    flush_git_only_patch(&mut patches, &mut git_old, &mut git_new, in_git_header);
+
+    if patches.is_empty() {
+        return Err("No file changes found in patch".to_string());
+    }
+    Ok(patches)
+}
+
+/// A git header with no hunks still describes a change: a pure rename, or an empty file created/deleted.
+fn flush_git_only_patch(
+    patches: &mut Vec<FilePatch>,
+    git_old: &mut Option<Option<String>>,
+    git_new: &mut Option<Option<String>>,
+    in_git_header: bool,
+) {
+    if in_git_header {
+        if let (Some(old_path), Some(new_path)) = (git_old.take(), git_new.take()) {
+            if old_path != new_path || old_path.is_none() || new_path.is_none() {
+                patches.push(FilePatch {
+                    old_path,
+                    new_path,
+                    hunks: Vec::new(),
+                });
+            }
+        }
+    }
+    *git_old = None;
+    *git_new = None;
+}
+
+fn split_git_diff_paths(rest: &str) -> Option<(String, String)> {
+    // `a/foo b/foo`; paths with spaces are ambiguous, so split at " b/"
+    let idx = rest.find(" b/")?;
+    Some((rest[..idx].to_string(), rest[idx + 1..].to_string()))
+}
+
+fn strip_prefix_component(path: &str) -> String {
+    path.strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path)
+        .to_string()
+}
+
+fn parse_header_path(raw: &str) -> Option<String> {
+    // Drop an optional tab-separated timestamp
+    let path = raw.split('\t').next().unwrap_or_default().trim_end();
+    if path == "/dev/null" {
+        None
+    } else {
+        Some(strip_prefix_component(path))
+    }
+}
+
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+fn parse_hunk(lines: &[&str], start: usize) -> Result<(Hunk, usize), String> {
+    let header = lines[start];
+    let invalid = || format!("Invalid hunk header at line {}: {}", start + 1, header);
+    let inner = header
+        .strip_prefix("@@ ")
+        .and_then(|h| h.split(" @@").next())
+        .ok_or_else(invalid)?;
+    let mut parts = inner.split_whitespace();
+    let old_range = parts.next().and_then(|p| p.strip_prefix('-')).ok_or_else(invalid)?;
+    let new_range = parts.next().and_then(|p| p.strip_prefix('+')).ok_or_else(invalid)?;
+    let (old_start, old_len) = parse_range(old_range).ok_or_else(invalid)?;
+    let (new_start, new_len) = parse_range(new_range).ok_or_else(invalid)?;
+
+    let mut hunk = Hunk {
+        old_start,
+        old_len,
+        new_start,
+        new_len,
+        lines: Vec::new(),
+        old_no_newline: false,
+        new_no_newline: false,
+    };
+    let (mut old_seen, mut new_seen) = (0, 0);
+    let mut i = start + 1;
+    while i < lines.len() && (old_seen < old_len || new_seen < new_len) {
+        let line = lines[i];
+        if let Some(text) = line.strip_prefix('+') {
+            hunk.lines.push(HunkLine::Add(text.to_string()));
+            new_seen += 1;
+        } else if let Some(text) = line.strip_prefix('-') {
+            hunk.lines.push(HunkLine::Remove(text.to_string()));
+            old_seen += 1;
+        } else if let Some(text) = line.strip_prefix(' ') {
+            hunk.lines.push(HunkLine::Context(text.to_string()));
+            old_seen += 1;
+            new_seen += 1;
+        } else if line.is_empty() {
+            // Some tools strip the trailing space from empty context lines
+            hunk.lines.push(HunkLine::Context(String::new()));
+            old_seen += 1;
+            new_seen += 1;
+        } else if line.starts_with('\\') {
+            mark_no_newline(&mut hunk);
+        } else {
+            return Err(format!(
+                "Unexpected line {} inside hunk {}: {}",
+                i + 1,
+                header,
+                line
+            ));
+        }
+        i += 1;
+    }
+    if old_seen != old_len || new_seen != new_len {
+        return Err(format!(
+            "Hunk {} is truncated: expected {} old and {} new lines, found {} and {}",
+            header, old_len, new_len, old_seen, new_seen
+        ));
+    }
+    // "\ No newline at end of file" may follow the final line of the hunk
+    if i < lines.len() && lines[i].starts_with('\\') {
+        mark_no_newline(&mut hunk);
+        i += 1;
+    }
+    Ok((hunk, i))
+}
+
+fn mark_no_newline(hunk: &mut Hunk) {
+    match hunk.lines.last() {
+        Some(HunkLine::Context(_)) => {
+            hunk.old_no_newline = true;
+            hunk.new_no_newline = true;
+        }
+        Some(HunkLine::Remove(_)) => hunk.old_no_newline = true,
+        Some(HunkLine::Add(_)) => hunk.new_no_newline = true,
+        None => {}
+    }
+}
+
+/// Apply hunks to `content`. Each hunk is located near its stated position, drifting by the
+/// offset of previously applied hunks. With `fuzz` > 0, up to that many leading and trailing
+/// context lines may be ignored when the exact context cannot be found.
+pub fn apply_hunks(content: &str, hunks: &[Hunk], fuzz: usize) -> Result<String, Vec<HunkRejection>> {
+    let had_trailing_newline = content.is_empty() || content.ends_with('\n');
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    let mut trailing_newline = had_trailing_newline;
+    let mut offset: isize = 0;
+    let mut rejections = Vec::new();
+    // Hunks must apply in order, so never search before the end of the previous hunk
+    let mut min_position = 0;
+
+    for (index, hunk) in hunks.iter().enumerate() {
+        let found = locate_hunk(&lines, hunk, offset, fuzz, min_position);
+        let (position, skip_front, skip_back) = match found {
+            Some(found) => found,
+            None => {
+                rejections.push(HunkRejection {
+                    hunk_number: index + 1,
+                    header: hunk.header(),
+                    reason: describe_mismatch(&lines, hunk, offset),
+                });
+                continue;
+            }
+        };
+
+        let old_lines = hunk.old_lines();
+        let new_lines = hunk.new_lines();
+        let old_slice = &old_lines[skip_front..old_lines.len() - skip_back];
+        let new_slice = &new_lines[skip_front..new_lines.len() - skip_back];
+        let replaced_end = position + old_slice.len();
+        let touches_eof = replaced_end == lines.len();
+
+        lines.splice(position..replaced_end, new_slice.iter().map(|s| s.to_string()));
+        if touches_eof && skip_back == 0 {
+            trailing_newline = !hunk.new_no_newline;
+        }
+
+        let expected_position = hunk_start(hunk) as isize + offset;
+        offset += (position as isize - expected_position) + new_slice.len() as isize - old_slice.len() as isize;
+        min_position = position + new_slice.len();
+    }
+
+    if !rejections.is_empty() {
+        return Err(rejections);
+    }
+
+    let mut result = lines.join("\n");
+    if trailing_newline && !lines.is_empty() {
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// zero-based index of the first old line of the hunk
+fn hunk_start(hunk: &Hunk) -> usize {
+    if hunk.old_len == 0 {
+        // For pure insertions the start is the line *after* which to insert
+        hunk.old_start
+    } else {
+        hunk.old_start.saturating_sub(1)
+    }
+}
+
+fn locate_hunk(
+    lines: &[String],
+    hunk: &Hunk,
+    offset: isize,
+    fuzz: usize,
+    min_position: usize,
+) -> Option<(usize, usize, usize)> {
+    let old_lines = hunk.old_lines();
+    let max_fuzz_front = hunk.leading_context().min(fuzz);
+    let max_fuzz_back = hunk.trailing_context().min(fuzz);
+
+    for level in 0..=fuzz {
+        let skip_front = level.min(max_fuzz_front);
+        let skip_back = level.min(max_fuzz_back);
+        if level > 0 && skip_front == 0 && skip_back == 0 {
+            break;
+        }
+        let wanted = &old_lines[skip_front..old_lines.len() - skip_back];
+        let expected = (hunk_start(hunk) as isize + offset + skip_front as isize).max(0) as usize;
+        if let Some(position) = search_outward(lines, wanted, expected, min_position) {
+            return Some((position, skip_front, skip_back));
+        }
+        if skip_front == max_fuzz_front && skip_back == max_fuzz_back {
+            break;
+        }
+    }
+    None
+}
+
+fn search_outward(lines: &[String], wanted: &[&str], expected: usize, min_position: usize) -> Option<usize> {
+    if wanted.len() > lines.len() {
+        return None;
+    }
+    let last_start = lines.len() - wanted.len();
+    let matches_at = |pos: usize| {
+        pos >= min_position
+            && pos <= last_start
+            && lines[pos..pos + wanted.len()]
+                .iter()
+                .zip(wanted)
+                .all(|(have, want)| have == want)
+    };
+    let expected = expected.min(last_start);
+    for distance in 0..=lines.len() {
+        if expected >= distance && matches_at(expected - distance) {
+            return Some(expected - distance);
+        }
+        if distance > 0 && matches_at(expected + distance) {
+            return Some(expected + distance);
+        }
+        if expected < distance && expected + distance > last_start {
+            break;
+        }
+    }
+    None
+}
+
+fn describe_mismatch(lines: &[String], hunk: &Hunk, offset: isize) -> String {
+    let old_lines = hunk.old_lines();
+    let expected = (hunk_start(hunk) as isize + offset).max(0) as usize;
+    for (i, want) in old_lines.iter().enumerate() {
+        match lines.get(expected + i) {
+            Some(have) if have == want => continue,
+            Some(have) => {
+                return format!(
+                    "context mismatch at line {}: expected {:?}, found {:?}",
+                    expected + i + 1,
+                    want,
+                    have
+                )
+            }
+            None => {
+                return format!(
+                    "file ends at line {} but the hunk expects {:?} at line {}",
+                    lines.len(),
+                    want,
+                    expected + i + 1
+                )
+            }
+        }
+    }
+    "hunk overlaps a previously applied hunk".to_string()
+}
+
+/// Render a unified diff between two texts, with `context` unchanged lines around each change.
+/// Returns an empty string when the texts are identical.
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str, context: usize) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(context)
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// Like [`unified_diff`], but lines that differ only in whitespace are treated as equal
+/// (the equivalent of `diff -w`). Output shows the lines as they appear in each file.
+pub fn unified_diff_ignore_whitespace(old: &str, new: &str, old_name: &str, new_name: &str, context: usize) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let strip = |line: &&str| line.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    let old_keys: Vec<String> = old_lines.iter().map(strip).collect();
+    let new_keys: Vec<String> = new_lines.iter().map(strip).collect();
+
+    let ops = capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys);
+    let groups = group_diff_ops(ops, context);
+    if groups.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for group in groups {
+        let (Some(first), Some(last)) = (group.first(), group.last()) else { continue };
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            format_range(old_range.start, old_range.len()),
+            format_range(new_range.start, new_range.len())
+        ));
+        for op in group {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            match tag {
+                DiffTag::Equal => old_lines[old_range].iter().for_each(|l| out.push_str(&format!(" {}\n", l))),
+                DiffTag::Delete => old_lines[old_range].iter().for_each(|l| out.push_str(&format!("-{}\n", l))),
+                DiffTag::Insert => new_lines[new_range].iter().for_each(|l| out.push_str(&format!("+{}\n", l))),
+                DiffTag::Replace => {
+                    old_lines[old_range].iter().for_each(|l| out.push_str(&format!("-{}\n", l)));
+                    new_lines[new_range].iter().for_each(|l| out.push_str(&format!("+{}\n", l)));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn format_range(start: usize, len: usize) -> String {
+    // Unified diff ranges are 1-based; an empty range names the line before it
+    let start = if len == 0 { start } else { start + 1 };
+    if len == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_git_diff_with_new_deleted_and_renamed_files() {
+        let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,2 +1,2 @@
+ fn main() {
+-}
++    }
+diff --git a/new.txt b/new.txt
+new file mode 100644
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++hello
+diff --git a/old.txt b/old.txt
+deleted file mode 100644
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+diff --git a/before.txt b/after.txt
+similarity index 100%
+rename from before.txt
+rename to after.txt
+";
+        let patches = parse_unified_diff(diff).unwrap();
+        assert_eq!(patches.len(), 4);
+        assert_eq!(patches[0].old_path.as_deref(), Some("src/lib.rs"));
+        assert!(patches[1].is_new_file());
+        assert!(patches[2].is_deletion());
+        assert!(patches[3].is_rename());
+        assert!(patches[3].hunks.is_empty());
+        assert_eq!(patches[3].new_path.as_deref(), Some("after.txt"));
+    }
+
+    #[test]
+    fn test_apply_hunks_with_offset_and_fuzz() {
+        let original = "zero\none\ntwo\nthree\nfour\nfive\n";
+        // stated position is off by one line, and the leading context has drifted
+        let diff = "\
+--- a/f
++++ b/f
+@@ -1,3 +1,3 @@
+ ONE
+ two
+-three
++THREE
+";
+        let patches = parse_unified_diff(diff).unwrap();
+        assert!(apply_hunks(original, &patches[0].hunks, 0).is_err());
+        let patched = apply_hunks(original, &patches[0].hunks, 1).unwrap();
+        assert_eq!(patched, "zero\none\ntwo\nTHREE\nfour\nfive\n");
+    }
+
+    #[test]
+    fn test_apply_hunks_reports_rejection() {
+        let diff = "\
+--- a/f
++++ b/f
+@@ -2,2 +2,2 @@
+ b
+-c
++C
+";
+        let patches = parse_unified_diff(diff).unwrap();
+        let rejections = apply_hunks("a\nb\nx\n", &patches[0].hunks, 0).unwrap_err();
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].hunk_number, 1);
+        assert!(rejections[0].reason.contains("expected \"c\", found \"x\""));
+    }
+
+    #[test]
+    fn test_unified_diff_round_trips_through_apply() {
+        let old = "a\nb\nc\nd\n";
+        let new = "a\nB\nc\nd\ne\n";
+        let diff = unified_diff(old, new, "a/f", "b/f", 1);
+        let patches = parse_unified_diff(&diff).unwrap();
+        assert_eq!(apply_hunks(old, &patches[0].hunks, 0).unwrap(), new);
+        assert!(unified_diff(old, old, "a/f", "b/f", 3).is_empty());
+    }
+
+    #[test]
+    fn test_unified_diff_ignore_whitespace() {
+        let old = "fn main() {\n    a();\n    b();\n}\n";
+        let new = "fn main() {\n  a();\n    c();\n}\n";
+        let diff = unified_diff_ignore_whitespace(old, new, "a/f", "b/f", 1);
+        assert!(diff.contains("@@ -2,3 +2,3 @@"), "unexpected diff: {}", diff);
+        assert!(diff.contains("-    b();\n+    c();"));
+        assert!(!diff.contains("-    a();"));
+        assert!(unified_diff_ignore_whitespace("a b\n", "ab\n", "a/f", "b/f", 3).is_empty());
+    }
+
+    #[test]
+    fn test_apply_hunks_no_newline_at_eof() {
+        let diff = "\
+--- a/f
++++ b/f
+@@ -1 +1 @@
+-a
++b
+\\ No newline at end of file
+";
+        let patches = parse_unified_diff(diff).unwrap();
+        assert_eq!(apply_hunks("a\n", &patches[0].hunks, 0).unwrap(), "b");
+    }
+}
@@ -0,0 +1,487 @@
+//! Path resolution that cannot be fooled by `..` segments or symlinks, and opening of files
+//! relative to a root directory handle so that validation and use see the same file.
+
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Same limit as Linux, so a symlink loop fails the same way it would in the kernel
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+
+enum Part {
+    Parent,
+    Normal(OsString),
+}
+
+/// Remove `.` components and redundant separators. `..` is kept: whether it may be
+/// collapsed depends on whether the preceding component is a symlink.
+pub fn normalize_lexically(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .collect()
+}
+
+/// Resolve `path` to an absolute path without symlinks, the way the kernel would walk it:
+/// each component is checked in turn, symlinks are replaced by their targets, and `..` steps
+/// back from the *resolved* prefix. Components that do not exist yet are appended as-is,
+/// so the result is also meaningful for files about to be created.
+pub fn resolve_path(path: &Path) -> io::Result<PathBuf> {
+    let absolute = if path.is_absolute() {
+        normalize_lexically(path)
+    } else {
+        std::env::current_dir()?.join(normalize_lexically(path))
+    };
+
+    let mut resolved = PathBuf::new();
+    let mut pending: Vec<Part> = Vec::new();
+    push_parts(&absolute, &mut resolved, &mut pending);
+
+    let mut follows = 0;
+    while let Some(part) = pending.pop() {
+        match part {
+            // Popping the root is a no-op, just like `/..` is `/`
+            Part::Parent => {
+                resolved.pop();
+            }
+            Part::Normal(name) => {
+                resolved.push(&name);
+                match fs::symlink_metadata(&resolved) {
+                    Ok(metadata) if metadata.file_type().is_symlink() => {
+                        follows += 1;
+                        if follows > MAX_SYMLINK_FOLLOWS {
+                            return Err(io::Error::other(format!(
+                                "too many levels of symbolic links resolving {}",
+                                path.display()
+                            )));
+                        }
+                        let target = fs::read_link(&resolved)?;
+                        resolved.pop();
+                        push_parts(&target, &mut resolved, &mut pending);
+                    }
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// Queue the components of `path` for resolution. An absolute path restarts from its root.
+fn push_parts(path: &Path, resolved: &mut PathBuf, pending: &mut Vec<Part>) {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                resolved.clear();
+                resolved.push(component.as_os_str());
+            }
+            Component::CurDir => {}
+            Component::ParentDir => parts.push(Part::Parent),
+            Component::Normal(name) => parts.push(Part::Normal(name.to_os_string())),
+        }
+    }
+    pending.extend(parts.into_iter().rev());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    /// write, creating the file if needed and truncating it otherwise
+    Write,
+}
+
+#[cfg(unix)]
+mod beneath {
+    use std::ffi::CString;
+    use std::ffi::OsStr;
+    use std::ffi::OsString;
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::os::fd::FromRawFd;
+    use std::os::fd::OwnedFd;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Component;
+    use std::path::Path;
+
+    const DIRECTORY_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+
+    pub fn to_cstring(name: &OsStr) -> io::Result<CString> {
+        CString::new(name.as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))
+    }
+
+    /// Turn the result of a call that returns a descriptor or -1 into an owned descriptor
+    pub fn check_fd(fd: libc::c_int) -> io::Result<OwnedFd> {
+        if fd < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            // SAFETY: fd was just returned by a successful open and is owned by nobody else
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        }
+    }
+
+    /// Turn the result of a call that returns 0 or -1 into a `Result`
+    pub fn check(result: libc::c_int) -> io::Result<()> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn names(relative: &Path) -> io::Result<Vec<&OsStr>> {
+        relative
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => Ok(name),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a plain relative path", relative.display()),
+                )),
+            })
+            .collect()
+    }
+
+    pub fn open_root(root: &Path) -> io::Result<OwnedFd> {
+        let root_name = to_cstring(root.as_os_str())?;
+        // SAFETY: root_name is a valid NUL-terminated string
+        check_fd(unsafe { libc::open(root_name.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) })
+    }
+
+    /// Open the directory `name` inside `dir` without following a symlink
+    pub fn open_subdirectory(dir: &OwnedFd, name: &OsStr) -> io::Result<OwnedFd> {
+        let name = to_cstring(name)?;
+        // SAFETY: dir is an open directory descriptor and name is NUL-terminated
+        check_fd(unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), DIRECTORY_FLAGS) })
+    }
+
+    /// Open the directory `relative` beneath `root`, one component at a time
+    pub fn open_directory(root: &Path, relative: &Path) -> io::Result<OwnedFd> {
+        let mut dir = open_root(root)?;
+        for name in names(relative)? {
+            dir = open_subdirectory(&dir, name)?;
+        }
+        Ok(dir)
+    }
+
+    /// Open the parent directory of `relative` beneath `root`, returning it with the final name
+    pub fn open_parent(root: &Path, relative: &Path) -> io::Result<(OwnedFd, CString)> {
+        let names = names(relative)?;
+        let Some((file_name, parents)) = names.split_last() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the root directory itself cannot be used here"));
+        };
+        let mut dir = open_root(root)?;
+        for name in parents {
+            dir = open_subdirectory(&dir, name)?;
+        }
+        Ok((dir, to_cstring(file_name)?))
+    }
+
+    /// The names in the directory open as `dir`, without `.` and `..`
+    pub fn read_directory(dir: OwnedFd) -> io::Result<Vec<OsString>> {
+        use std::os::fd::IntoRawFd;
+
+        let fd = dir.into_raw_fd();
+        // SAFETY: fd is an open directory descriptor; on success the stream owns it
+        let stream = unsafe { libc::fdopendir(fd) };
+        if stream.is_null() {
+            let error = io::Error::last_os_error();
+            // SAFETY: fdopendir failed, so fd is still ours to close
+            unsafe { libc::close(fd) };
+            return Err(error);
+        }
+        let mut names = Vec::new();
+        loop {
+            // SAFETY: stream is a valid directory stream until closedir below
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                break;
+            }
+            // SAFETY: readdir returned a valid entry whose name is NUL-terminated
+            let name = unsafe { std::ffi::CStr::from_ptr((*entry).d_name.as_ptr()) };
+            let name = OsStr::from_bytes(name.to_bytes());
+            if name != "." && name != ".." {
+                names.push(name.to_os_string());
+            }
+        }
+        // SAFETY: stream is valid and not used afterwards
+        unsafe { libc::closedir(stream) };
+        Ok(names)
+    }
+
+    /// Create every missing directory of `relative` beneath `root`
+    pub fn create_directories(root: &Path, relative: &Path) -> io::Result<()> {
+        let mut dir = open_root(root)?;
+        for name in names(relative)? {
+            let c_name = to_cstring(name)?;
+            // SAFETY: dir is an open directory descriptor and c_name is NUL-terminated
+            let created = check(unsafe { libc::mkdirat(dir.as_raw_fd(), c_name.as_ptr(), 0o777) });
+            match created {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+                _ => {}
+            }
+            dir = open_subdirectory(&dir, name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Metadata of `relative` beneath `root`, or of `root` itself when `relative` is empty. The
+/// final component is not followed when it is a symlink, and is opened without reading it.
+#[cfg(unix)]
+pub fn metadata_beneath(root: &Path, relative: &Path) -> io::Result<fs::Metadata> {
+    use std::os::fd::AsRawFd;
+
+    if relative.as_os_str().is_empty() {
+        return File::from(beneath::open_root(root)?).metadata();
+    }
+    let (dir, name) = beneath::open_parent(root, relative)?;
+    #[cfg(target_os = "linux")]
+    let flags = libc::O_PATH;
+    // without O_PATH, don't block on a FIFO
+    #[cfg(not(target_os = "linux"))]
+    let flags = libc::O_RDONLY | libc::O_NONBLOCK;
+    // SAFETY: dir is an open directory descriptor and name is NUL-terminated
+    let file = beneath::check_fd(unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags | libc::O_NOFOLLOW | libc::O_CLOEXEC) })?;
+    File::from(file).metadata()
+}
+
+/// Open `relative` beneath the directory `root`. Every component is opened relative to the
+/// previous one with `O_NOFOLLOW`, so a symlink swapped into the path after it was validated
+/// makes the open fail instead of escaping `root`.
+#[cfg(unix)]
+pub fn open_beneath(root: &Path, relative: &Path, mode: OpenMode) -> io::Result<File> {
+    use std::os::fd::AsRawFd;
+
+    let (dir, name) = beneath::open_parent(root, relative)?;
+    let flags = match mode {
+        OpenMode::Read => libc::O_RDONLY,
+        OpenMode::Write => libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+    } | libc::O_NOFOLLOW
+        | libc::O_CLOEXEC;
+    // SAFETY: dir is an open directory descriptor and name is NUL-terminated
+    let file = beneath::check_fd(unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags, 0o666 as libc::c_uint) })?;
+    Ok(File::from(file))
+}
+
+/// The names in the directory `relative` beneath `root`, opened the same way as `open_beneath`
+#[cfg(unix)]
+pub fn read_dir_beneath(root: &Path, relative: &Path) -> io::Result<Vec<OsString>> {
+    beneath::read_directory(beneath::open_directory(root, relative)?)
+}
+
+/// Create the directory `relative` beneath `root` and any missing parents, refusing to pass
+/// through symlinks
+#[cfg(unix)]
+pub fn create_dir_all_beneath(root: &Path, relative: &Path) -> io::Result<()> {
+    beneath::create_directories(root, relative)
+}
+
+/// Rename `relative` beneath `root` to `to_relative` beneath `to_root`, with both parent
+/// directories opened the same way as `open_beneath`
+#[cfg(unix)]
+pub fn rename_beneath(root: &Path, relative: &Path, to_root: &Path, to_relative: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (from_dir, from_name) = beneath::open_parent(root, relative)?;
+    let (to_dir, to_name) = beneath::open_parent(to_root, to_relative)?;
+    // SAFETY: both directories are open descriptors and both names are NUL-terminated
+    beneath::check(unsafe {
+        libc::renameat(from_dir.as_raw_fd(), from_name.as_ptr(), to_dir.as_raw_fd(), to_name.as_ptr())
+    })
+}
+
+/// Remove the file, or with `directory` the empty directory, `relative` beneath `root`.
+/// A symlink in the final component is removed itself, never its target.
+#[cfg(unix)]
+pub fn remove_beneath(root: &Path, relative: &Path, directory: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (dir, name) = beneath::open_parent(root, relative)?;
+    let flags = if directory { libc::AT_REMOVEDIR } else { 0 };
+    // SAFETY: dir is an open directory descriptor and name is NUL-terminated
+    beneath::check(unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) })
+}
+
+/// Without `openat`, re-resolve right before use and refuse if the path has changed.
+#[cfg(not(unix))]
+fn unchanged(root: &Path, relative: &Path) -> io::Result<PathBuf> {
+    let path = root.join(relative);
+    if resolve_path(&path)? != path {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} changed while it was being opened", path.display()),
+        ));
+    }
+    Ok(path)
+}
+
+#[cfg(not(unix))]
+pub fn open_beneath(root: &Path, relative: &Path, mode: OpenMode) -> io::Result<File> {
+    let path = unchanged(root, relative)?;
+    match mode {
+        OpenMode::Read => File::open(&path),
+        OpenMode::Write => File::create(&path),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn metadata_beneath(root: &Path, relative: &Path) -> io::Result<fs::Metadata> {
+    fs::symlink_metadata(unchanged(root, relative)?)
+}
+
+#[cfg(not(unix))]
+pub fn read_dir_beneath(root: &Path, relative: &Path) -> io::Result<Vec<OsString>> {
+    fs::read_dir(unchanged(root, relative)?)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn create_dir_all_beneath(root: &Path, relative: &Path) -> io::Result<()> {
+    fs::create_dir_all(unchanged(root, relative)?)
+}
+
+#[cfg(not(unix))]
+pub fn rename_beneath(root: &Path, relative: &Path, to_root: &Path, to_relative: &Path) -> io::Result<()> {
+    fs::rename(unchanged(root, relative)?, unchanged(to_root, to_relative)?)
+}
+
+#[cfg(not(unix))]
+pub fn remove_beneath(root: &Path, relative: &Path, directory: bool) -> io::Result<()> {
+    let path = unchanged(root, relative)?;
+    if directory {
+        fs::remove_dir(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    fn canonical_temp_dir() -> (TempDir, PathBuf) {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        (temp_dir, root)
+    }
+
+    #[test]
+    fn test_normalize_lexically_keeps_parent_components() {
+        assert_eq!(normalize_lexically(Path::new("/a/./b//c/../d")), PathBuf::from("/a/b/c/../d"));
+    }
+
+    #[test]
+    fn test_resolve_dot_dot_of_missing_path() {
+        let (_temp_dir, root) = canonical_temp_dir();
+        let escape = root.join("allowed/../../etc/newfile");
+        let resolved = resolve_path(&escape).unwrap();
+        assert_eq!(resolved, root.parent().unwrap().join("etc/newfile"));
+        assert!(!resolved.starts_with(&root));
+        assert_eq!(resolve_path(Path::new("/../../etc")).unwrap(), PathBuf::from("/etc"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_follows_symlinks_before_dot_dot() {
+        use std::os::unix::fs::symlink;
+        let (_temp_dir, root) = canonical_temp_dir();
+        let (_outside_dir, outside) = canonical_temp_dir();
+        fs::create_dir_all(outside.join("deep/er")).unwrap();
+        fs::create_dir(root.join("allowed")).unwrap();
+        symlink(outside.join("deep/er"), root.join("allowed/link")).unwrap();
+
+        // `link/..` is the parent of the link target, not `allowed`
+        let resolved = resolve_path(&root.join("allowed/link/../secret")).unwrap();
+        assert_eq!(resolved, outside.join("deep/secret"));
+
+        // Relative symlinks resolve against the directory containing them
+        symlink("../allowed", root.join("allowed/self")).unwrap();
+        assert_eq!(resolve_path(&root.join("allowed/self/x")).unwrap(), root.join("allowed/x"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_dangling_symlink_and_loops() {
+        use std::os::unix::fs::symlink;
+        let (_temp_dir, root) = canonical_temp_dir();
+        symlink("/nonexistent-target/file", root.join("dangling")).unwrap();
+        assert_eq!(resolve_path(&root.join("dangling")).unwrap(), PathBuf::from("/nonexistent-target/file"));
+
+        symlink(root.join("loop_b"), root.join("loop_a")).unwrap();
+        symlink(root.join("loop_a"), root.join("loop_b")).unwrap();
+        assert!(resolve_path(&root.join("loop_a/file")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_open_beneath_refuses_swapped_symlink() {
+        use std::os::unix::fs::symlink;
+        let (_temp_dir, root) = canonical_temp_dir();
+        let (_outside_dir, outside) = canonical_temp_dir();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/secret"), "fine").unwrap();
+
+        let mut content = String::new();
+        open_beneath(&root, Path::new("dir/secret"), OpenMode::Read)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "fine");
+
+        // Replace the validated directory with a symlink to somewhere else
+        fs::remove_dir_all(root.join("dir")).unwrap();
+        symlink(&outside, root.join("dir")).unwrap();
+        assert!(open_beneath(&root, Path::new("dir/secret"), OpenMode::Read).is_err());
+
+        // A symlinked final component is refused too, for writes as well as reads
+        symlink(outside.join("secret"), root.join("file_link")).unwrap();
+        assert!(open_beneath(&root, Path::new("file_link"), OpenMode::Write).is_err());
+        assert_eq!(fs::read_to_string(outside.join("secret")).unwrap(), "secret");
+
+        assert!(open_beneath(&root, Path::new("../escape"), OpenMode::Write).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_operations_refuse_swapped_symlink() {
+        use std::os::unix::fs::symlink;
+        let (_temp_dir, root) = canonical_temp_dir();
+        let (_outside_dir, outside) = canonical_temp_dir();
+        fs::write(outside.join("secret"), "secret").unwrap();
+
+        create_dir_all_beneath(&root, Path::new("dir/sub")).unwrap();
+        fs::write(root.join("dir/file"), "fine").unwrap();
+        assert_eq!(metadata_beneath(&root, Path::new("dir/file")).unwrap().len(), 4);
+        assert!(metadata_beneath(&root, Path::new("")).unwrap().is_dir());
+        let mut names = read_dir_beneath(&root, Path::new("dir")).unwrap();
+        names.sort();
+        assert_eq!(names, vec![OsString::from("file"), OsString::from("sub")]);
+        rename_beneath(&root, Path::new("dir/file"), &root, Path::new("dir/sub/moved")).unwrap();
+        assert_eq!(fs::read_to_string(root.join("dir/sub/moved")).unwrap(), "fine");
+        remove_beneath(&root, Path::new("dir/sub/moved"), false).unwrap();
+        remove_beneath(&root, Path::new("dir/sub"), true).unwrap();
+        assert!(!root.join("dir/sub").exists());
+
+        // Once the directory is a symlink out of the root, nothing goes through it
+        fs::remove_dir_all(root.join("dir")).unwrap();
+        symlink(&outside, root.join("dir")).unwrap();
+        assert!(read_dir_beneath(&root, Path::new("dir")).is_err());
+        assert!(metadata_beneath(&root, Path::new("dir/secret")).is_err());
+        assert!(create_dir_all_beneath(&root, Path::new("dir/new")).is_err());
+        assert!(rename_beneath(&root, Path::new("dir/secret"), &root, Path::new("stolen")).is_err());
+        assert!(remove_beneath(&root, Path::new("dir/secret"), false).is_err());
+        assert!(!outside.join("new").exists());
+        assert_eq!(fs::read_to_string(outside.join("secret")).unwrap(), "secret");
+    }
+}
@@ -0,0 +1,119 @@
+//! Progress notifications (`notifications/progress`) for requests whose params carry
+//! `_meta.progressToken`. Long-running tools report how far they got, and the total when it is
+//! known, through a `Reporter`.
+
+use crate::mcp::types::Progress;
+use crate::mcp::utilities::notify;
+use serde_json::Value;
+use std::future::Future;
+use std::time::Duration;
+use std::time::Instant;
+
+/// minimum time between two notifications, so that fast operations send none at all
+const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+tokio::task_local! {
+    static PROGRESS_TOKEN: Value;
+}
+
+/// The progress token of a request, from its params
+pub fn token_of(params: Option<&Value>) -> Option<Value> {
+    params?.pointer("/_meta/progressToken").cloned()
+}
+
+/// Run a request's handler, reporting progress to `token` if the client asked for it
+pub async fn scope<F: Future>(token: Option<Value>, future: F) -> F::Output {
+    match token {
+        Some(token) => PROGRESS_TOKEN.scope(token, future).await,
+        None => future.await,
+    }
+}
+
+/// Reports the progress of one operation of the current request. Progress must only grow.
+pub struct Reporter {
+    token: Option<Value>,
+    interval: Duration,
+    last_report: Instant,
+}
+
+impl Reporter {
+    pub fn for_current_request() -> Reporter {
+        Reporter {
+            token: PROGRESS_TOKEN.try_with(Value::clone).ok(),
+            interval: REPORT_INTERVAL,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Send a progress notification, unless the client did not ask for progress or the last one
+    /// was sent too recently
+    pub fn report(&mut self, progress: u64, total: Option<u64>, message: impl FnOnce() -> String) {
+        if let Some(progress) = self.next(progress, total, message) {
+            notify("notifications/progress", serde_json::to_value(progress).ok());
+        }
+    }
+
+    fn next(&mut self, progress: u64, total: Option<u64>, message: impl FnOnce() -> String) -> Option<Progress> {
+        let token = self.token.as_ref()?;
+        if self.last_report.elapsed() < self.interval {
+            return None;
+        }
+        self.last_report = Instant::now();
+        Some(Progress {
+            progress_token: token.clone(),
+            progress,
+            total,
+            message: Some(message()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_reports_only_with_a_progress_token() {
+        let params = json!({"name": "grep_search", "_meta": {"progressToken": 7}});
+        assert_eq!(token_of(Some(&params)), Some(json!(7)));
+        assert_eq!(token_of(Some(&json!({"name": "grep_search"}))), None);
+
+        assert!(Reporter::for_current_request().next(1, None, String::new).is_none());
+
+        let progress = scope(Some(json!(7)), async {
+            let mut reporter = Reporter::for_current_request();
+            // nothing is sent before the first interval has passed
+            assert!(reporter.next(1, Some(10), String::new).is_none());
+            reporter.interval = Duration::ZERO;
+            reporter.next(2, Some(10), || "2 of 10 files scanned".to_string())
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(progress).unwrap(),
+            json!({"progressToken": 7, "progress": 2, "total": 10, "message": "2 of 10 files scanned"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_a_token_get_no_progress() {
+        assert_eq!(token_of(None), None);
+        assert_eq!(token_of(Some(&json!({"_meta": {}}))), None);
+        assert_eq!(token_of(Some(&json!({"_meta": "progressToken"}))), None);
+
+        // a request running alongside one that asked for progress does not report to its token
+        let without = tokio::spawn(scope(None, async {
+            let mut reporter = Reporter::for_current_request();
+            reporter.interval = Duration::ZERO;
+            reporter.next(1, None, String::new)
+        }));
+        let with = scope(Some(json!("other")), async {
+            let mut reporter = Reporter::for_current_request();
+            reporter.interval = Duration::ZERO;
+            reporter.next(1, None, String::new)
+        });
+        assert!(with.await.is_some());
+        assert!(without.await.unwrap().is_none());
+    }
+}
@@ -97,7 +97,7 @@ pub async fn prompts_list(
                 description: Some("Overwrite contents of a file".to_string()),
                 arguments: Some(vec![
                     PromptArgument {
-                        name: "file_path".to_string(),
+                        name: "path".to_string(),
                         description: Some("Path to file to overwrite".to_string()),
                         required: Some(true),
                     },
@@ -113,6 +113,19 @@ pub async fn prompts_list(
     Ok(response)
 }
 
+/// A required argument of a prompt, or an invalid params error when it is missing
+fn argument<'a>(request: &'a GetPromptRequest, name: &str) -> HandlerResult<&'a str> {
+    request
+        .arguments
+        .as_ref()
+        .and_then(|arguments| arguments.get(name))
+        .and_then(|value| value.as_str())
+        .ok_or_else(|| {
+            let message = format!("Missing argument {} for prompt {}", name, request.name);
+            json!({"code": ErrorCode::InvalidParams as i32, "message": message}).into_handler_error()
+        })
+}
+
 pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResult> {
     let response = match request.name.as_str() {
         "current_time" => PromptResult {
@@ -123,7 +136,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
                     type_name: "text".to_string(),
                     text: format!(
                         "What's the time of {}?",
-                        request.arguments.as_ref().unwrap()["city"].as_str().unwrap()
+                        argument(&request, "city")?
                     ),
                 },
             }]),
@@ -146,8 +159,8 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
                     type_name: "text".to_string(),
                     text: format!(
                         "Edit file {} with message: {}",
-                        request.arguments.as_ref().unwrap()["file_path"].as_str().unwrap(),
-                        request.arguments.as_ref().unwrap()["commit_message"].as_str().unwrap()
+                        argument(&request, "file_path")?,
+                        argument(&request, "commit_message")?
                     ),
                 },
             }]),
@@ -160,7 +173,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
                     type_name: "text".to_string(),
                     text: format!(
                         "Read file {}",
-                        request.arguments.as_ref().unwrap()["file_path"].as_str().unwrap()
+                        argument(&request, "file_path")?
                     ),
                 },
             }]),
@@ -173,7 +186,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
                     type_name: "text".to_string(),
                     text: format!(
                         "List contents of directory {}",
-                        request.arguments.as_ref().unwrap()["path"].as_str().unwrap()
+                        argument(&request, "path")?
                     ),
                 },
             }]),
@@ -186,8 +199,8 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
                     type_name: "text".to_string(),
                     text: format!(
                         "Move/rename {} to {}",
-                        request.arguments.as_ref().unwrap()["source_path"].as_str().unwrap(),
-                        request.arguments.as_ref().unwrap()["target_path"].as_str().unwrap()
+                        argument(&request, "source_path")?,
+                        argument(&request, "target_path")?
                     ),
                 },
             }]),
@@ -200,7 +213,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
                     type_name: "text".to_string(),
                     text: format!(
                         "Get info for {}",
-                        request.arguments.as_ref().unwrap()["path"].as_str().unwrap()
+                        argument(&request, "path")?
                     ),
                 },
             }]),
@@ -213,7 +226,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
                     type_name: "text".to_string(),
                     text: format!(
                         "Create directory {}",
-                        request.arguments.as_ref().unwrap()["path"].as_str().unwrap()
+                        argument(&request, "path")?
                     ),
                 },
             }]),
@@ -226,7 +239,7 @@ pub async fn prompts_get(request: GetPromptRequest) -> HandlerResult<PromptResul
                     type_name: "text".to_string(),
                     text: format!(
                         "Overwrite {} with new content",
-                        request.arguments.as_ref().unwrap()["file_path"].as_str().unwrap()
+                        argument(&request, "path")?
                     ),
                 },
             }]),
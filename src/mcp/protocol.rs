@@ -0,0 +1,119 @@
+//! MCP protocol revisions. The revision is negotiated in `initialize`, and features the client's
+//! revision doesn't know are left out of what the server sends.
+
+use crate::mcp::session;
+use crate::mcp::types::Tool;
+use serde_json::Value;
+use std::sync::RwLock;
+
+/// supported revisions, newest first
+pub const SUPPORTED_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+pub const LATEST_VERSION: &str = SUPPORTED_VERSIONS[0];
+
+/// the revision of the stdio client; sessions keep their own
+static VERSION: RwLock<&'static str> = RwLock::new(LATEST_VERSION);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// JSON-RPC batches
+    Batching,
+    /// `annotations` on tools
+    ToolAnnotations,
+    /// `outputSchema` on tools and `structuredContent` in their results
+    StructuredContent,
+    /// `title` on tools and the server info
+    Titles,
+    /// `resource_link` content in tool results
+    ResourceLinks,
+}
+
+impl Feature {
+    /// The revision that introduced the feature, and the one that removed it
+    fn revisions(self) -> (&'static str, Option<&'static str>) {
+        match self {
+            Feature::Batching => ("2025-03-26", Some("2025-06-18")),
+            Feature::ToolAnnotations => ("2025-03-26", None),
+            Feature::StructuredContent | Feature::Titles | Feature::ResourceLinks => ("2025-06-18", None),
+        }
+    }
+}
+
+/// The revision to use with a client asking for `requested`: the same one if supported,
+/// otherwise the latest, which the client may then reject
+pub fn negotiate(requested: &str) -> &'static str {
+    SUPPORTED_VERSIONS
+        .iter()
+        .find(|version| **version == requested)
+        .copied()
+        .unwrap_or(LATEST_VERSION)
+}
+
+pub fn version() -> &'static str {
+    session::with_state(|state| state.protocol_version.unwrap_or(LATEST_VERSION))
+        .unwrap_or_else(|| *VERSION.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+pub fn set_version(version: &'static str) {
+    if session::with_state(|state| state.protocol_version = Some(version)).is_none() {
+        *VERSION.write().unwrap_or_else(|e| e.into_inner()) = version;
+    }
+}
+
+fn supports_in(version: &str, feature: Feature) -> bool {
+    // revisions are dates, so they compare as strings
+    let (introduced, removed) = feature.revisions();
+    version >= introduced && removed.is_none_or(|removed| version < removed)
+}
+
+/// Whether the negotiated revision has `feature`
+pub fn supports(feature: Feature) -> bool {
+    supports_in(version(), feature)
+}
+
+/// Drop the fields of a `tools/list` entry that the negotiated revision doesn't know
+pub fn adapt_tool(tool: &mut Tool) {
+    if !supports(Feature::Titles) {
+        tool.title = None;
+    }
+    if !supports(Feature::ToolAnnotations) {
+        tool.annotations = None;
+    }
+    if !supports(Feature::StructuredContent) {
+        tool.output_schema = None;
+    }
+}
+
+/// Drop the parts of a serialized `CallToolResult` that the negotiated revision doesn't know
+pub fn adapt_tool_result(result: &mut Value) {
+    let Some(result) = result.as_object_mut() else {
+        return;
+    };
+    if !supports(Feature::StructuredContent) {
+        result.remove("structuredContent");
+    }
+    if !supports(Feature::ResourceLinks) {
+        if let Some(Value::Array(content)) = result.get_mut("content") {
+            content.retain(|item| item.get("type").and_then(Value::as_str) != Some("resource_link"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiation_and_features_by_revision() {
+        assert_eq!(negotiate("2025-03-26"), "2025-03-26");
+        assert_eq!(negotiate("2024-11-05"), "2024-11-05");
+        assert_eq!(negotiate("2099-01-01"), LATEST_VERSION);
+
+        assert!(!supports_in("2024-11-05", Feature::Batching));
+        assert!(supports_in("2025-03-26", Feature::Batching));
+        assert!(!supports_in("2025-06-18", Feature::Batching));
+        assert!(supports_in("2025-03-26", Feature::ToolAnnotations));
+        assert!(!supports_in("2025-03-26", Feature::StructuredContent));
+        assert!(supports_in("2025-06-18", Feature::ResourceLinks));
+    }
+}
@@ -5,27 +5,177 @@ use rpc_router::IntoHandlerError;
 use url::Url;
 use serde_json::json;
 use serde::{Deserialize, Serialize};
-use crate::mcp::utilities::get_allowed_directories;
+use base64::Engine;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use crate::mcp::paths::OpenMode;
+use crate::mcp::redact;
+use crate::mcp::utilities::{get_allowed_directories, is_path_allowed, validate_path_or_error, validate_file_size_or_error, Access};
 
+/// number of resources returned per `resources/list` page
+const RESOURCES_PAGE_SIZE: usize = 100;
 
-pub async fn resources_list(
-    _request: Option<ListResourcesRequest>,
-) -> HandlerResult<ListResourcesResult> {
-    let mut resources = Vec::new();
-    
-    // Always include the allowed_directories resource
-    resources.push(Resource {
+/// Files with one of these types are returned as text; anything else is returned as text only
+/// if it is valid UTF-8
+fn is_text_mime(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || ["application/json", "application/xml", "application/javascript", "application/toml"].contains(&mime)
+}
+
+fn guess_mime_type(path: &Path) -> Option<String> {
+    mime_guess::from_path(path).first().map(|mime| mime.essence_str().to_string())
+}
+
+fn allowed_directories_resource() -> Resource {
+    Resource {
         uri: Url::parse("file:///api/allowed_directories").unwrap(),
         name: "Allowed Directories".to_string(),
         description: Some("List of directories that can be accessed".to_string()),
         mime_type: Some("application/json".to_string()),
-    }); 
-    
-    let response = ListResourcesResult {
+    }
+}
+
+fn invalid_params(message: String) -> rpc_router::HandlerError {
+    json!({"code": ErrorCode::InvalidParams as i32, "message": message}).into_handler_error()
+}
+
+/// Where the next `resources/list` page starts: after `after` in the allowed directory at
+/// index `root`. Clients treat it as opaque, so it is sent base64-encoded.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    root: usize,
+    after: PathBuf,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor serializes");
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json)
+    }
+
+    fn decode(cursor: &str) -> Option<Cursor> {
+        let json = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+/// Append the readable files under `dir` that sort after `after` to `files`, in path order,
+/// until it holds `limit`. Directories that sort entirely before `after` are skipped unread,
+/// so a page costs about as much as the files on it. Like `collect_files`, `.git` is skipped
+/// and symlinks are not followed.
+fn walk_files_after(dir: &Path, after: Option<&Path>, limit: usize, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if files.len() >= limit {
+            return;
+        }
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else { continue };
+        if file_type.is_dir() {
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            match after {
+                Some(after) if after.starts_with(&path) => walk_files_after(&path, Some(after), limit, files),
+                Some(after) if path.as_path() < after => {}
+                _ => walk_files_after(&path, None, limit, files),
+            }
+        } else if file_type.is_file()
+            && after.is_none_or(|after| path.as_path() > after)
+            && is_path_allowed(&path, Access::Read)
+        {
+            files.push(path);
+        }
+    }
+}
+
+fn file_resource(root: &Path, file: &Path) -> Option<Resource> {
+    Some(Resource {
+        uri: Url::from_file_path(file).ok()?,
+        name: file.strip_prefix(root).unwrap_or(file).to_string_lossy().into_owned(),
+        description: None,
+        mime_type: guess_mime_type(file),
+    })
+}
+
+/// List the allowed_directories resource and the files under the allowed directories, in a
+/// stable order. The cursor names the last file listed, and the next page resumes the walk
+/// after it.
+pub async fn resources_list(
+    request: Option<ListResourcesRequest>,
+) -> HandlerResult<ListResourcesResult> {
+    let cursor = match request.and_then(|request| request.cursor) {
+        Some(cursor) => Some(Cursor::decode(&cursor).ok_or_else(|| invalid_params(format!("Invalid cursor: {}", cursor)))?),
+        None => None,
+    };
+
+    // The allowed_directories resource always starts the first page
+    let mut resources = Vec::new();
+    if cursor.is_none() {
+        resources.push(allowed_directories_resource());
+    }
+
+    // One file more than fits shows whether there is another page
+    let (start, after) = cursor.map_or((0, None), |cursor| (cursor.root, Some(cursor.after)));
+    let capacity = RESOURCES_PAGE_SIZE - resources.len();
+    let dirs = get_allowed_directories();
+    let mut found: Vec<(usize, PathBuf)> = Vec::new();
+    for (index, dir) in dirs.iter().enumerate().skip(start) {
+        let root = Path::new(dir);
+        let after = if index == start { after.as_deref() } else { None };
+        let mut files = Vec::new();
+        if root.is_file() {
+            if after.is_none() && is_path_allowed(root, Access::Read) {
+                files.push(root.to_path_buf());
+            }
+        } else {
+            walk_files_after(root, after, capacity + 1 - found.len(), &mut files);
+        }
+        found.extend(files.into_iter().map(|file| (index, file)));
+        if found.len() > capacity {
+            break;
+        }
+    }
+
+    let next_cursor = if found.len() > capacity {
+        found.truncate(capacity);
+        found.last().map(|(root, after)| Cursor { root: *root, after: after.clone() }.encode())
+    } else {
+        None
+    };
+    resources.extend(found.iter().filter_map(|(index, file)| file_resource(Path::new(&dirs[*index]), file)));
+    Ok(ListResourcesResult {
         resources,
+        next_cursor,
+    })
+}
+
+/// One template per allowed directory, so clients can address any file under it
+pub async fn resource_templates_list(
+    _request: Option<ListResourceTemplatesRequest>,
+) -> HandlerResult<ListResourceTemplatesResult> {
+    let resource_templates = get_allowed_directories()
+        .into_iter()
+        .filter_map(|dir| {
+            let uri = Url::from_directory_path(&dir).ok()?;
+            Some(ResourceTemplate {
+                uri_template: format!("{}{{+path}}", uri),
+                name: format!("Files in {}", dir),
+                description: Some(format!("A file under the allowed directory {}, by relative path", dir)),
+                mime_type: None,
+            })
+        })
+        .collect();
+    Ok(ListResourceTemplatesResult {
+        resource_templates,
         next_cursor: None,
-    };
-    Ok(response)
+    })
 }
 
 pub async fn resource_read(request: ReadResourceRequest) -> HandlerResult<ReadResourceResult> {
@@ -33,18 +183,64 @@ pub async fn resource_read(request: ReadResourceRequest) -> HandlerResult<ReadRe
         "/api/allowed_directories" => {
             let allowed_dirs = get_allowed_directories();
             ReadResourceResult {
-                contents: vec![TextResourceContents {
+                contents: vec![ResourceContent {
                     uri: request.uri.clone(),
                     mime_type: Some("application/json".to_string()),
-                    text: serde_json::to_string_pretty(&allowed_dirs).unwrap(),
+                    text: Some(serde_json::to_string_pretty(&allowed_dirs).unwrap()),
+                    blob: None,
                 }],
             }
         },
-        _ => return Err(json!({"code": -32602, "message": "Resource not found"}).into_handler_error()),
+        _ if request.uri.scheme() == "file" => ReadResourceResult {
+            contents: vec![read_file_resource(&request.uri)?],
+        },
+        _ => return Err(invalid_params("Resource not found".to_string())),
     };
     Ok(response)
 }
 
+/// Read a `file://` resource, as text when it is text and as a base64 blob otherwise
+fn read_file_resource(uri: &Url) -> HandlerResult<ResourceContent> {
+    let path = uri
+        .to_file_path()
+        .map_err(|_| invalid_params(format!("Invalid file URI: {}", uri)))?;
+    let resolved = validate_path_or_error(&path, Access::Read).map_err(invalid_params)?;
+    let metadata = fs::metadata(&resolved.path).map_err(|_| invalid_params("Resource not found".to_string()))?;
+    if !metadata.is_file() {
+        return Err(invalid_params(format!("Not a file: {}", uri)));
+    }
+    validate_file_size_or_error(&path, metadata.len()).map_err(invalid_params)?;
+
+    let mut bytes = Vec::new();
+    resolved
+        .open(OpenMode::Read)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| invalid_params(format!("Error reading {}: {}", uri, e)))?;
+
+    let mime_type = guess_mime_type(&resolved.path);
+    let text_expected = mime_type.as_deref().is_none_or(is_text_mime);
+    let content = match String::from_utf8(bytes) {
+        Ok(text) if text_expected => ResourceContent {
+            uri: uri.clone(),
+            mime_type: Some(mime_type.unwrap_or_else(|| "text/plain".to_string())),
+            text: Some(redact::redact_output("resources/read", &resolved.path, text)),
+            blob: None,
+        },
+        Ok(text) => binary_content(uri, mime_type, text.into_bytes()),
+        Err(e) => binary_content(uri, mime_type, e.into_bytes()),
+    };
+    Ok(content)
+}
+
+fn binary_content(uri: &Url, mime_type: Option<String>, bytes: Vec<u8>) -> ResourceContent {
+    ResourceContent {
+        uri: uri.clone(),
+        mime_type: Some(mime_type.unwrap_or_else(|| "application/octet-stream".to_string())),
+        text: None,
+        blob: Some(base64::engine::general_purpose::STANDARD.encode(bytes)),
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, RpcParams)]
 pub struct GetAllowedDirectoriesRequest {
 }
@@ -52,10 +248,101 @@ pub struct GetAllowedDirectoriesRequest {
 pub async fn allowed_directories(_request: GetAllowedDirectoriesRequest) -> HandlerResult<ReadResourceResult> {
     let allowed_dirs = get_allowed_directories();
     Ok(ReadResourceResult {
-        contents: vec![TextResourceContents {
+        contents: vec![ResourceContent {
             uri: Url::parse("file:///api/allowed_directories").unwrap(),
             mime_type: Some("application/json".to_string()),
-            text: serde_json::to_string_pretty(&allowed_dirs).unwrap(),
+            text: Some(serde_json::to_string_pretty(&allowed_dirs).unwrap()),
+            blob: None,
         }],
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::TEST_ENV_LOCK;
+    use std::env;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_file_resources_list_read_and_templates() {
+        let _env_guard = TEST_ENV_LOCK.lock().await;
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &root);
+        fs::create_dir(root.join("many")).unwrap();
+        for i in 0..120 {
+            fs::write(root.join("many").join(format!("{:03}.txt", i)), "x").unwrap();
+        }
+        fs::write(root.join("notes.md"), "# Notes\n").unwrap();
+        fs::write(root.join("logo.png"), [0x89, b'P', b'N', b'G', 0x00, 0xff]).unwrap();
+        fs::write(root.join(".env"), "SECRET=1\n").unwrap();
+
+        // 1 allowed_directories resource + 122 files, 100 per page
+        let first = resources_list(None).await.unwrap();
+        assert_eq!(first.resources.len(), 100);
+        assert_eq!(first.resources[0].uri.as_str(), "file:///api/allowed_directories");
+        assert!(first.next_cursor.is_some());
+        let second = resources_list(Some(ListResourcesRequest { cursor: first.next_cursor })).await.unwrap();
+        assert_eq!(second.resources.len(), 23);
+        assert_eq!(second.next_cursor, None);
+        let names: Vec<&str> = second.resources.iter().map(|r| r.name.as_str()).collect();
+        assert!(names.contains(&"notes.md"));
+        assert!(!names.contains(&".env"));
+        // The pages together list every file once, in order
+        let all: Vec<&str> = first.resources[1..].iter().chain(&second.resources).map(|r| r.name.as_str()).collect();
+        let mut sorted = all.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(all, sorted);
+        assert_eq!(all.len(), 122);
+        assert!(resources_list(Some(ListResourcesRequest { cursor: Some("bogus".to_string()) })).await.is_err());
+
+        let read = |path: &Path| resource_read(ReadResourceRequest { uri: Url::from_file_path(path).unwrap(), meta: None });
+        let text = read(&root.join("notes.md")).await.unwrap();
+        assert_eq!(text.contents[0].mime_type.as_deref(), Some("text/markdown"));
+        assert_eq!(text.contents[0].text.as_deref(), Some("# Notes\n"));
+        assert!(text.contents[0].blob.is_none());
+        let image = read(&root.join("logo.png")).await.unwrap();
+        assert_eq!(image.contents[0].mime_type.as_deref(), Some("image/png"));
+        assert_eq!(image.contents[0].blob.as_deref(), Some("iVBORwD/"));
+        assert!(read(&root.join(".env")).await.is_err());
+        assert!(read(&root.join("missing.txt")).await.is_err());
+        assert!(read(Path::new("/etc/hostname")).await.is_err());
+
+        let templates = resource_templates_list(None).await.unwrap();
+        assert_eq!(
+            templates.resource_templates[0].uri_template,
+            format!("{}{{+path}}", Url::from_directory_path(&root).unwrap())
+        );
+
+        env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_resources_outside_the_allowed_directories_are_refused() {
+        let _env_guard = TEST_ENV_LOCK.lock().await;
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path().canonicalize().unwrap();
+        let root = base.join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(base.join("secret.txt"), "secret\n").unwrap();
+        fs::create_dir(root.join("dir")).unwrap();
+        std::os::unix::fs::symlink(base.join("secret.txt"), root.join("link.txt")).unwrap();
+        env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &root);
+
+        let read = |uri: Url| resource_read(ReadResourceRequest { uri, meta: None });
+        assert!(read(Url::from_file_path(base.join("secret.txt")).unwrap()).await.is_err());
+        assert!(read(Url::from_file_path(root.join("link.txt")).unwrap()).await.is_err());
+        assert!(read(Url::from_file_path(root.join("dir")).unwrap()).await.is_err());
+        assert!(read(Url::parse("https://example.com/secret.txt").unwrap()).await.is_err());
+
+        // the symlink to the file outside is not listed either
+        let listed = resources_list(None).await.unwrap();
+        let names: Vec<&str> = listed.resources.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["Allowed Directories"]);
+
+        env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+    }
+}
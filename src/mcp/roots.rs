@@ -0,0 +1,133 @@
+//! Roots shared by the client (MCP `roots/list`). When the client supports roots, access is
+//! limited to the intersection of its roots with the allowed directories from env and config.
+
+use crate::mcp::client;
+use crate::mcp::logging;
+use crate::mcp::server_log;
+use crate::mcp::session;
+use crate::mcp::subscriptions;
+use crate::mcp::types::ListRootsResult;
+use serde_json::json;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::RwLock;
+use url::Url;
+
+/// whether the stdio client advertised the `roots` capability in `initialize`; sessions keep
+/// their own roots
+static CLIENT_SUPPORTS_ROOTS: AtomicBool = AtomicBool::new(false);
+
+/// the client's roots, canonicalized; `None` until the client has answered `roots/list`
+static CLIENT_ROOTS: RwLock<Option<Vec<PathBuf>>> = RwLock::new(None);
+
+pub fn set_client_supports_roots(supported: bool) {
+    if session::with_state(|state| state.client_supports_roots = supported).is_none() {
+        CLIENT_SUPPORTS_ROOTS.store(supported, Ordering::SeqCst);
+    }
+}
+
+fn client_supports_roots() -> bool {
+    session::with_state(|state| state.client_supports_roots)
+        .unwrap_or_else(|| CLIENT_SUPPORTS_ROOTS.load(Ordering::SeqCst))
+}
+
+pub fn client_roots() -> Option<Vec<PathBuf>> {
+    session::with_state(|state| state.client_roots.clone())
+        .unwrap_or_else(|| CLIENT_ROOTS.read().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+pub fn set_client_roots(roots: Option<Vec<PathBuf>>) {
+    match session::current() {
+        Some(session) => session.state().client_roots = roots,
+        None => *CLIENT_ROOTS.write().unwrap_or_else(|e| e.into_inner()) = roots,
+    }
+}
+
+/// Whether a resolved path lies inside one of the client's roots. Always true for clients
+/// without roots support.
+pub fn is_within_client_roots(path: &Path) -> bool {
+    match client_roots() {
+        Some(roots) => roots.iter().any(|root| path.starts_with(root)),
+        None => true,
+    }
+}
+
+/// Narrow allowed directories to the client's roots: a client root inside an allowed directory
+/// replaces it, and an allowed directory inside a client root is kept.
+pub fn intersect(allowed: Vec<String>) -> Vec<String> {
+    let Some(client_roots) = client_roots() else {
+        return allowed;
+    };
+    let mut dirs: Vec<String> = Vec::new();
+    for dir in allowed {
+        let canonical = Path::new(&dir).canonicalize().unwrap_or_else(|_| PathBuf::from(&dir));
+        for client_root in &client_roots {
+            let dir = if client_root.starts_with(&canonical) {
+                client_root.to_string_lossy().into_owned()
+            } else if canonical.starts_with(client_root) {
+                dir.clone()
+            } else {
+                continue;
+            };
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs
+}
+
+/// Parse the client's answer to `roots/list`. Roots that are not `file://` URIs are ignored.
+pub fn parse_roots(result: ListRootsResult) -> Vec<PathBuf> {
+    result
+        .roots
+        .iter()
+        .filter_map(|root| Url::parse(&root.uri).ok()?.to_file_path().ok())
+        .map(|path| path.canonicalize().unwrap_or(path))
+        .collect()
+}
+
+/// Ask the client for its roots, after `notifications/initialized` and whenever the client
+/// reports that they changed. Spawned, because the response arrives through the stdin loop.
+pub async fn refresh() {
+    if !client_supports_roots() {
+        return;
+    }
+    let result = client::request("roots/list", None)
+        .await
+        .and_then(|value| serde_json::from_value::<ListRootsResult>(value).map_err(|e| e.to_string()));
+    match result {
+        Ok(result) => {
+            let roots = parse_roots(result);
+            server_log::log_event("client_roots_updated", json!({ "roots": roots }));
+            set_client_roots(Some(roots));
+            // the watcher follows the stdio client's roots; sessions share it unnarrowed
+            if session::current().is_none() {
+                subscriptions::sync_watches();
+            }
+        }
+        Err(e) => {
+            logging::warning("roots", format!("Could not get the client's roots: {}", e));
+            server_log::log_event("client_roots_failed", json!({ "error": e }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::types::Root;
+
+    #[test]
+    fn test_parse_roots_keeps_file_uris() {
+        let result = ListRootsResult {
+            roots: vec![
+                Root { uri: "file:///srv/project".to_string(), name: Some("project".to_string()) },
+                Root { uri: "https://example.com/repo".to_string(), name: None },
+            ],
+        };
+        assert_eq!(parse_roots(result), vec![PathBuf::from("/srv/project")]);
+    }
+}
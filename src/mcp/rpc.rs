@@ -0,0 +1,470 @@
+//! JSON-RPC dispatch shared by the transports: a message from the client, or a batch of them,
+//! goes in, and the response to send back comes out.
+
+use crate::mcp::audit;
+use crate::mcp::audit::Outcome;
+use crate::mcp::cancellation;
+use crate::mcp::cancellation::InFlight;
+use crate::mcp::client;
+use crate::mcp::config;
+use crate::mcp::logging;
+use crate::mcp::progress;
+use crate::mcp::protocol;
+use crate::mcp::protocol::Feature;
+use crate::mcp::roots;
+use crate::mcp::server_log;
+use crate::mcp::session;
+use crate::mcp::tools;
+use crate::mcp::types::CancelledNotification;
+use crate::mcp::types::ErrorCode;
+use crate::mcp::types::JsonRpcError;
+use crate::mcp::types::JsonRpcResponse;
+use crate::mcp::types::ToolCallRequestParams;
+use crate::mcp::utilities::notifications_cancelled;
+use crate::mcp::utilities::notifications_initialized;
+use rpc_router::Error;
+use rpc_router::Request;
+use rpc_router::Router;
+use serde_json::json;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// The methods the server answers besides the tools. Tools are routed by their names next to
+/// these, so a tool named like one of them would replace it.
+pub const METHODS: &[&str] = &[
+    "initialize",
+    "ping",
+    "logging/setLevel",
+    "prompts/list",
+    "prompts/get",
+    "resources/list",
+    "resources/read",
+    "resources/templates/list",
+    "resources/subscribe",
+    "resources/unsubscribe",
+    "resources/allowed_directories",
+    "tools/list",
+    "tools/call",
+];
+
+/// Routes client messages to the handlers, with at most a fixed number of requests handled at
+/// the same time across all transports and sessions
+#[derive(Clone)]
+pub struct Dispatcher {
+    router: Router,
+    request_slots: Arc<Semaphore>,
+}
+
+impl Dispatcher {
+    pub fn new(router: Router, max_concurrent_requests: usize) -> Dispatcher {
+        Dispatcher {
+            router,
+            request_slots: Arc::new(Semaphore::new(max_concurrent_requests.max(1))),
+        }
+    }
+
+    /// Handle a message or a batch. Notifications and responses are handled right away, and
+    /// requests are tracked for cancellation before this returns; the future handles them and
+    /// resolves to the response, if any.
+    pub fn dispatch(&self, message: Value) -> impl Future<Output = Option<Value>> + Send + 'static {
+        let (is_batch, messages) = match message {
+            Value::Array(batch) => (true, batch),
+            message => (false, vec![message]),
+        };
+        // Only some protocol revisions allow batches
+        let batch_error = if !is_batch {
+            None
+        } else if messages.is_empty() {
+            Some("Empty batch")
+        } else if !protocol::supports(Feature::Batching) {
+            Some("Batches are not supported by the negotiated protocol version")
+        } else {
+            None
+        };
+        let mut requests = JoinSet::new();
+        if batch_error.is_none() {
+            for message in messages.into_iter().filter_map(handle_message) {
+                match message {
+                    Ok(rpc_request) => requests.spawn(self.run_request(rpc_request)),
+                    Err(response) => requests.spawn(async move { Some(response) }),
+                };
+            }
+        }
+        async move {
+            if let Some(message) = batch_error {
+                return Some(error_response(Value::Null, ErrorCode::InvalidRequest, message));
+            }
+            let mut responses = Vec::new();
+            while let Some(response) = requests.join_next().await {
+                match response {
+                    Ok(response) => responses.extend(response),
+                    // a handler panicked; its request id is gone with its task
+                    Err(e) => {
+                        server_log::log_event("request_failed", json!({ "error": e.to_string() }));
+                        responses.push(error_response(Value::Null, ErrorCode::InternalError, "Internal error"));
+                    }
+                }
+            }
+            if is_batch {
+                // a batch of notifications only gets no response
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            } else {
+                responses.pop()
+            }
+        }
+    }
+
+    /// Start tracking a request for cancellation right away, and return the future that waits
+    /// for a free slot and handles it in the current session
+    fn run_request(&self, rpc_request: Request) -> impl Future<Output = Option<Value>> + Send + 'static {
+        let in_flight = cancellation::track(&rpc_request.id);
+        let router = self.router.clone();
+        let request_slots = self.request_slots.clone();
+        session::scope(session::current(), async move {
+            let _slot = request_slots.acquire_owned().await;
+            handle_request(&router, rpc_request, in_flight).await
+        })
+    }
+}
+
+/// The response to a line or body that isn't JSON
+pub fn parse_error() -> Value {
+    error_response(Value::Null, ErrorCode::ParseError, "Parse error")
+}
+
+fn error_response(id: Value, code: ErrorCode, message: &str) -> Value {
+    json!(JsonRpcError::new(id, code as i32, message))
+}
+
+/// Handle a notification or a response from the client. Returns the message as a request if
+/// it is one, so that a response is expected, or the error response for an invalid request.
+pub fn handle_message(message: Value) -> Option<Result<Request, Value>> {
+    let Some(object) = message.as_object() else {
+        return Some(Err(error_response(Value::Null, ErrorCode::InvalidRequest, "Invalid request: expected an object")));
+    };
+    let id = match object.get("id") {
+        None => None,
+        Some(id @ (Value::String(_) | Value::Number(_) | Value::Null)) => Some(id.clone()),
+        Some(_) => {
+            let message = "Invalid request: id must be a string, a number or null";
+            return Some(Err(error_response(Value::Null, ErrorCode::InvalidRequest, message)));
+        }
+    };
+    let is_response = object.contains_key("result") || object.contains_key("error");
+    let method = match object.get("method") {
+        Some(Value::String(method)) => method.as_str(),
+        Some(_) => {
+            let message = "Invalid request: method must be a string";
+            return Some(Err(error_response(id.unwrap_or_default(), ErrorCode::InvalidRequest, message)));
+        }
+        // Response to a request the server sent, delivered to the waiting task if any
+        None if is_response => {
+            client::handle_response(&message);
+            return None;
+        }
+        None => {
+            let message = "Invalid request: missing method";
+            return Some(Err(error_response(id.unwrap_or_default(), ErrorCode::InvalidRequest, message)));
+        }
+    };
+    let Some(id) = id else {
+        // Notifications, no response required
+        handle_notification(method, object.get("params"));
+        return None;
+    };
+    if object.get("jsonrpc") != Some(&json!("2.0")) {
+        return Some(Err(error_response(id, ErrorCode::InvalidRequest, "Invalid request: jsonrpc must be \"2.0\"")));
+    }
+    if object.get("params").is_some_and(|params| !params.is_object() && !params.is_array()) {
+        return Some(Err(error_response(id, ErrorCode::InvalidRequest, "Invalid request: params must be an object or an array")));
+    }
+    Some(Request::from_value(message).map_err(|_| error_response(id, ErrorCode::InvalidRequest, "Invalid request")))
+}
+
+fn handle_notification(method: &str, params: Option<&Value>) {
+    match method {
+        "notifications/initialized" => {
+            notifications_initialized();
+            session::spawn(roots::refresh());
+        }
+        "notifications/roots/list_changed" => {
+            session::spawn(roots::refresh());
+        }
+        "notifications/cancelled" => {
+            match params.cloned().map(serde_json::from_value::<CancelledNotification>) {
+                Some(Ok(params)) => notifications_cancelled(params),
+                // notifications get no response, even when they are invalid
+                _ => server_log::log_event("invalid_notification", json!({ "method": method, "params": params })),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Call the handler for a request and return its response, if any
+async fn handle_request(router: &Router, mut rpc_request: Request, in_flight: InFlight) -> Option<Value> {
+    let id = rpc_request.id.clone();
+    let method = rpc_request.method.clone();
+    let progress_token = progress::token_of(rpc_request.params.as_ref());
+    let mut tool_name = None;
+    if rpc_request.method == "tools/call" {
+        let params = match rpc_request.params.map(serde_json::from_value::<ToolCallRequestParams>) {
+            Some(Ok(params)) => params,
+            Some(Err(e)) => {
+                let message = format!("Invalid tools/call params: {}", e);
+                return Some(error_response(id, ErrorCode::InvalidParams, &message));
+            }
+            None => return Some(error_response(id, ErrorCode::InvalidParams, "Missing tools/call params")),
+        };
+        // only tools, not the other methods of the router
+        if !tools::is_tool(&params.name) {
+            let message = format!("Unknown tool: {}", params.name);
+            return Some(error_response(id, ErrorCode::InvalidParams, &message));
+        }
+        tool_name = Some(params.name.clone());
+        rpc_request = Request {
+            id: id.clone(),
+            method: params.name,
+            // `arguments` is optional for tools without required ones
+            params: Some(params.arguments.unwrap_or_else(|| json!({}))),
+        }
+    }
+    let result = match &tool_name {
+        // Tool calls are recorded in the audit log
+        Some(tool) => {
+            // with auditing required, a call that couldn't be recorded doesn't run at all
+            if config::current().require_audit {
+                if let Err(e) = audit::check_writable() {
+                    let message = format!("{} was not run: {}", tool, e);
+                    return Some(error_response(id, ErrorCode::InternalError, &message));
+                }
+            }
+            let (result, operation) = audit::track(in_flight.run(progress::scope(progress_token, router.call(rpc_request)))).await;
+            let outcome = match &result {
+                Some(Ok(call_response)) => Outcome::of_tool_result(&call_response.value),
+                Some(Err(error)) => Outcome::Error(error.error.to_string()),
+                None => Outcome::Error("Request cancelled".to_string()),
+            };
+            if let Err(e) = audit::emit(&id, tool, &operation, &outcome) {
+                logging::warning("audit", e);
+            }
+            logging::tool_call(tool, &operation, &outcome);
+            result
+        }
+        None => in_flight.run(progress::scope(progress_token, router.call(rpc_request))).await,
+    };
+    // A cancelled request gets no response
+    let result = result?;
+    let response = match result {
+        Ok(call_response) => {
+            let mut value = call_response.value;
+            if value.is_null() {
+                return None;
+            }
+            if tool_name.is_some() {
+                protocol::adapt_tool_result(&mut value);
+            }
+            json!(JsonRpcResponse::new(id, value))
+        }
+        Err(error) => {
+            let (code, message) = match (&error.error, &tool_name) {
+                // Error from JSON-RPC call
+                (Error::Handler(handler), _) => match handler.get::<Value>() {
+                    Some(error_value) => {
+                        return Some(json!({
+                            "jsonrpc": "2.0",
+                            "error": error_value,
+                            "id": id
+                        }))
+                    }
+                    None => (ErrorCode::InternalError, format!("{} failed", method)),
+                },
+                (Error::MethodUnknown, _) => (ErrorCode::MethodNotFound, format!("Method not found: {}", method)),
+                (Error::ParamsParsing(e), Some(tool)) => {
+                    (ErrorCode::InvalidParams, format!("Invalid arguments for tool {}: {}", tool, e))
+                }
+                (Error::ParamsMissingButRequested, Some(tool)) => {
+                    (ErrorCode::InvalidParams, format!("Missing arguments for tool {}", tool))
+                }
+                (Error::ParamsParsing(e), None) => (ErrorCode::InvalidParams, format!("Invalid params: {}", e)),
+                (Error::ParamsMissingButRequested, None) => (ErrorCode::InvalidParams, "Missing params".to_string()),
+                (error, _) => (ErrorCode::InternalError, format!("Internal error: {}", error)),
+            };
+            error_response(id, code, &message)
+        }
+    };
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::prompts::prompts_get;
+    use crate::mcp::tools::register_tools;
+    use crate::mcp::tools::TOOLS;
+    use crate::mcp::utilities::initialize;
+    use crate::mcp::utilities::ping;
+    use crate::mcp::TEST_ENV_LOCK;
+    use rpc_router::Handler;
+    use rpc_router::RouterBuilder;
+
+    fn dispatcher() -> Dispatcher {
+        let builder = RouterBuilder::default()
+            .append_dyn("initialize", initialize.into_dyn())
+            .append_dyn("ping", ping.into_dyn())
+            .append_dyn("prompts/get", prompts_get.into_dyn());
+        Dispatcher::new(register_tools(builder).build(), 4)
+    }
+
+    /// Dispatch in a session of its own, so that garbage can't change the process-wide state
+    async fn dispatch(message: Value) -> Option<Value> {
+        let dispatcher = dispatcher();
+        let session = session::create();
+        let response = session::scope(Some(session.clone()), async move { dispatcher.dispatch(message).await }).await;
+        session::remove(&session.id);
+        response
+    }
+
+    fn error_code(response: &Option<Value>) -> Option<i64> {
+        response.as_ref()?["error"]["code"].as_i64()
+    }
+
+    /// A small xorshift generator, so that failures can be reproduced
+    struct Garbage(u64);
+
+    impl Garbage {
+        fn next(&mut self, below: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % below
+        }
+
+        fn value(&mut self, depth: u32) -> Value {
+            let strings = ["2.0", "tools/call", "initialize", "ping", "prompts/get", "notifications/cancelled", "nope", ""];
+            let keys = ["jsonrpc", "id", "method", "params", "name", "arguments", "requestId", "path", "paths", "content", "pattern", "edits", "result"];
+            match self.next(if depth == 0 { 5 } else { 8 }) {
+                0 => Value::Null,
+                1 => json!(self.next(3) == 0),
+                2 => json!(self.next(1000) as i64 - 500),
+                3 if self.next(2) == 0 => json!(TOOLS[self.next(TOOLS.len() as u64) as usize].name),
+                3 => json!(strings[self.next(strings.len() as u64) as usize]),
+                4 => json!("\u{0}\u{ffff}🙂../../etc/passwd"),
+                5 | 6 => {
+                    let mut object = serde_json::Map::new();
+                    for _ in 0..self.next(5) {
+                        let key = keys[self.next(keys.len() as u64) as usize];
+                        object.insert(key.to_string(), self.value(depth - 1));
+                    }
+                    Value::Object(object)
+                }
+                _ => Value::Array((0..self.next(4)).map(|_| self.value(depth - 1)).collect()),
+            }
+        }
+
+        /// Anything at all, a request with garbage params, or a tool call with garbage arguments
+        fn message(&mut self) -> Value {
+            let id = self.value(0);
+            match self.next(4) {
+                0 => self.value(4),
+                1 => json!({"jsonrpc": "2.0", "id": id, "method": self.value(0), "params": self.value(3)}),
+                _ => {
+                    let name = TOOLS[self.next(TOOLS.len() as u64) as usize].name;
+                    let arguments = self.value(3);
+                    json!({"jsonrpc": "2.0", "id": id, "method": "tools/call", "params": {"name": name, "arguments": arguments}})
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_requests_get_json_rpc_errors() {
+        let _env_guard = TEST_ENV_LOCK.lock().await;
+        let request = |method: &str, params: Value| json!({"jsonrpc": "2.0", "id": 7, "method": method, "params": params});
+
+        let response = dispatch(request("tools/call", json!({"name": "no_such_tool", "arguments": {}}))).await;
+        assert_eq!(error_code(&response), Some(ErrorCode::InvalidParams as i64));
+        assert!(response.unwrap()["error"]["message"].as_str().unwrap().contains("Unknown tool"));
+        let response = dispatch(request("tools/call", json!({"name": "read_file", "arguments": {"path": 3}}))).await;
+        assert!(response.as_ref().unwrap()["error"]["message"].as_str().unwrap().contains("Invalid arguments for tool read_file"));
+        // the router's other methods are not tools
+        let response = dispatch(request("tools/call", json!({"name": "initialize"}))).await;
+        assert_eq!(error_code(&response), Some(ErrorCode::InvalidParams as i64));
+        let response = dispatch(request("tools/call", json!({"name": "get_local_time"}))).await;
+        assert!(response.unwrap()["result"]["content"][0]["text"].as_str().unwrap().starts_with("Local time"));
+        let response = dispatch(json!({"jsonrpc": "2.0", "id": 7, "method": "tools/call"})).await;
+        assert_eq!(error_code(&response), Some(ErrorCode::InvalidParams as i64));
+        let response = dispatch(request("prompts/get", json!({"name": "current_time"}))).await;
+        assert_eq!(error_code(&response), Some(ErrorCode::InvalidParams as i64));
+
+        let response = dispatch(request("no/such/method", json!({}))).await;
+        assert_eq!(error_code(&response), Some(ErrorCode::MethodNotFound as i64));
+        assert_eq!(response.unwrap()["id"], json!(7));
+        for invalid in [json!(42), json!({"id": 1}), json!({"id": {}, "method": "ping"}), json!({"id": 1, "method": 5})] {
+            assert_eq!(error_code(&dispatch(invalid).await), Some(ErrorCode::InvalidRequest as i64));
+        }
+        assert_eq!(error_code(&dispatch(json!({"id": 1, "method": "ping"})).await), Some(ErrorCode::InvalidRequest as i64));
+        assert_eq!(error_code(&dispatch(request("ping", json!("params"))).await), Some(ErrorCode::InvalidRequest as i64));
+
+        // notifications and stray responses never get a response, even when invalid
+        assert_eq!(dispatch(json!({"jsonrpc": "2.0", "method": "notifications/cancelled"})).await, None);
+        assert_eq!(dispatch(json!({"jsonrpc": "2.0", "method": "notifications/cancelled", "params": 1})).await, None);
+        assert_eq!(dispatch(json!({"jsonrpc": "2.0", "id": "server-0", "result": {}})).await, None);
+        assert_eq!(error_code(&Some(parse_error())), Some(ErrorCode::ParseError as i64));
+    }
+
+    #[tokio::test]
+    async fn test_garbage_never_panics() {
+        let _env_guard = TEST_ENV_LOCK.lock().await;
+        // the calls can write anywhere they are allowed to, so only allow a scratch directory
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", temp_dir.path().canonicalize().unwrap());
+        std::env::remove_var("MCP_RS_FILESYSTEM_CONFIG");
+        crate::mcp::config::init(None).unwrap();
+        let mut garbage = Garbage(0x9e3779b97f4a7c15);
+        for _ in 0..2000 {
+            let message = garbage.message();
+            if let Some(response) = dispatch(message.clone()).await {
+                let responses = match response {
+                    Value::Array(responses) => responses,
+                    response => vec![response],
+                };
+                for response in responses {
+                    assert_eq!(response["jsonrpc"], json!("2.0"), "for {}", message);
+                    assert!(response.get("id").is_some(), "for {}", message);
+                    assert!(response.get("result").is_some() != response.get("error").is_some(), "for {}", message);
+                    assert_ne!(response["error"]["code"], json!(ErrorCode::InternalError as i32), "for {}", message);
+                }
+            }
+        }
+        std::env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+    }
+
+    #[tokio::test]
+    async fn test_unwritable_audit_log() {
+        let _env_guard = TEST_ENV_LOCK.lock().await;
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("server.logs.jsonl");
+        std::env::set_var("MCP_LOG_FILE_PATH", &log_path);
+        std::env::set_var("MCP_AUDIT_LOG_PATH", temp_dir.path().join("missing/audit.jsonl"));
+        let request = json!({"jsonrpc": "2.0", "id": 3, "method": "tools/call", "params": {"name": "get_local_time"}});
+
+        // by default the call goes ahead and the failure is in the server log
+        let response = dispatch(request.clone()).await.unwrap();
+        assert!(response["result"]["content"][0]["text"].as_str().unwrap().starts_with("Local time"));
+        assert!(std::fs::read_to_string(&log_path).unwrap().contains("\"event\":\"audit_log_failed\""));
+
+        // when auditing is required, the call is refused
+        let config = crate::mcp::config::Config { require_audit: true, ..Default::default() };
+        crate::mcp::config::set(config).unwrap();
+        let response = dispatch(request).await.unwrap();
+        assert!(response["error"]["message"].as_str().unwrap().starts_with("get_local_time was not run: Failed to open audit log"));
+
+        crate::mcp::config::init(None).unwrap();
+        std::env::remove_var("MCP_AUDIT_LOG_PATH");
+        std::env::remove_var("MCP_LOG_FILE_PATH");
+    }
+}
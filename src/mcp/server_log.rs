@@ -0,0 +1,86 @@
+//! The server log: a JSONL file with every incoming message and security-relevant events
+
+use chrono::Utc;
+use dirs::data_local_dir;
+use dirs::home_dir;
+use dirs::state_dir;
+use serde_json::json;
+use serde_json::Value;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// set by a server built with its own log file, which takes precedence over `MCP_LOG_FILE_PATH`
+static LOG_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+pub fn get_log_directory() -> PathBuf {
+    if cfg!(target_os = "macos") {
+        // macOS: ~/Library/Logs/Claude
+        home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("Library/Logs/Claude")
+    } else if cfg!(target_os = "windows") {
+        // Windows: %LOCALAPPDATA%\Claude\logs
+        data_local_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("Claude")
+            .join("logs")
+    } else {
+        // Linux: ~/.local/state/claude/logs
+        state_dir()
+            .unwrap_or_else(|| {
+                home_dir()
+                    .unwrap_or_else(|| PathBuf::from("."))
+                    .join(".local/state")
+            })
+            .join("claude")
+            .join("logs")
+    }
+}
+
+pub fn set_log_path(path: Option<PathBuf>) {
+    *LOG_PATH.write().unwrap_or_else(|e| e.into_inner()) = path;
+}
+
+/// The path set for the server, `MCP_LOG_FILE_PATH`, or `rs_filesystem.logs.jsonl` in the
+/// platform's log directory
+pub fn log_path() -> PathBuf {
+    if let Some(path) = LOG_PATH.read().unwrap_or_else(|e| e.into_inner()).clone() {
+        return path;
+    }
+    env::var("MCP_LOG_FILE_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| get_log_directory().join("rs_filesystem.logs.jsonl"))
+}
+
+/// Append a message received from an HTTP or socket client to the server log, as stdio logs each
+/// line
+pub fn log_received(message: &Value) {
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(log_path()) {
+        let _ = writeln!(file, "{}", message);
+    }
+}
+
+/// Append a message sent to the client to the server log, after the incoming messages
+pub fn log_message(message: &Value) {
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(log_path()) {
+        let _ = writeln!(file, "{}\n", message);
+    }
+}
+
+/// Append an event such as a blocked file or a redaction to the server log.
+/// Logging must never fail a request, so errors are ignored.
+pub fn log_event(event: &str, details: Value) {
+    let mut entry = json!({
+        "timestamp": Utc::now().to_rfc3339(),
+        "event": event,
+    });
+    if let (Some(entry), Value::Object(details)) = (entry.as_object_mut(), details) {
+        entry.extend(details);
+    }
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(log_path()) {
+        let _ = writeln!(file, "{}", entry);
+    }
+}
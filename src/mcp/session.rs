@@ -0,0 +1,239 @@
+//! Client sessions of the HTTP and Unix socket transports. Each session has an id, its own MCP
+//! state (negotiated revision, log level, roots, subscriptions, workspace root), and a queue of
+//! the messages the server sends it outside of responses (notifications and server requests).
+//! With stdio there are no sessions: the state is process-wide and messages go to stdout.
+
+use crate::mcp::types::Implementation;
+use crate::mcp::types::LoggingLevel;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// messages queued for a session while no SSE stream is open; older ones are kept, newer dropped
+const OUTBOUND_QUEUE_SIZE: usize = 1024;
+
+static SESSIONS: LazyLock<Mutex<HashMap<String, Arc<Session>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+tokio::task_local! {
+    static CURRENT: Arc<Session>;
+}
+
+pub struct Session {
+    pub id: String,
+    sender: mpsc::Sender<Value>,
+    /// taken by the open SSE stream, and put back when it closes
+    receiver: Mutex<Option<mpsc::Receiver<Value>>>,
+    /// cancelled when the session ends, which closes its SSE stream or connection
+    pub ended: CancellationToken,
+    state: Mutex<State>,
+    /// when the client last made a request or closed its stream
+    last_active: Mutex<Instant>,
+}
+
+/// What a client set up for itself; `None` means the default
+#[derive(Debug, Default)]
+pub struct State {
+    pub protocol_version: Option<&'static str>,
+    pub log_level: Option<LoggingLevel>,
+    pub client_info: Option<Implementation>,
+    pub client_supports_roots: bool,
+    /// the client's roots, which narrow the allowed directories for this session only
+    pub client_roots: Option<Vec<PathBuf>>,
+    pub subscriptions: HashSet<PathBuf>,
+    pub working_directory: Option<PathBuf>,
+}
+
+impl Session {
+    /// Queue a notification or server request for the session's stream. It is dropped if the
+    /// queue is full, so responses must not be sent this way.
+    pub fn send(&self, message: &Value) {
+        let _ = self.sender.try_send(message.clone());
+    }
+
+    /// The queue of outbound messages, unless an SSE stream already has it
+    pub fn take_receiver(&self) -> Option<mpsc::Receiver<Value>> {
+        self.receiver.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    pub fn return_receiver(&self, receiver: mpsc::Receiver<Value>) {
+        *self.receiver.lock().unwrap_or_else(|e| e.into_inner()) = Some(receiver);
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Note that the client is still there
+    pub fn touch(&self) {
+        *self.last_active.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    /// Whether the client has been gone for `timeout` at `now`: no request since, and no open
+    /// stream
+    fn is_idle(&self, now: Instant, timeout: Duration) -> bool {
+        let has_stream = self.receiver.lock().unwrap_or_else(|e| e.into_inner()).is_none();
+        let last_active = *self.last_active.lock().unwrap_or_else(|e| e.into_inner());
+        !has_stream && now.saturating_duration_since(last_active) > timeout
+    }
+}
+
+/// Start a session with a new, unguessable id
+pub fn create() -> Arc<Session> {
+    let (sender, receiver) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+    let session = Arc::new(Session {
+        id: uuid::Uuid::new_v4().to_string(),
+        sender,
+        receiver: Mutex::new(Some(receiver)),
+        ended: CancellationToken::new(),
+        state: Mutex::new(State::default()),
+        last_active: Mutex::new(Instant::now()),
+    });
+    SESSIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(session.id.clone(), session.clone());
+    session
+}
+
+pub fn get(id: &str) -> Option<Arc<Session>> {
+    SESSIONS.lock().unwrap_or_else(|e| e.into_inner()).get(id).cloned()
+}
+
+/// End a session, returning whether it existed
+pub fn remove(id: &str) -> bool {
+    let session = SESSIONS.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+    session.inspect(|session| session.ended.cancel()).is_some()
+}
+
+/// End the sessions whose clients have been gone for `timeout`, for clients that disconnect
+/// without ending their session. Returns their ids.
+pub fn remove_idle(timeout: Duration) -> Vec<String> {
+    let now = Instant::now();
+    let idle: Vec<String> = all()
+        .into_iter()
+        .filter(|session| session.is_idle(now, timeout))
+        .map(|session| session.id.clone())
+        .collect();
+    idle.into_iter().filter(|id| remove(id)).collect()
+}
+
+pub fn all() -> Vec<Arc<Session>> {
+    SESSIONS.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect()
+}
+
+/// Send a message to every session
+pub fn broadcast(message: &Value) {
+    for session in SESSIONS.lock().unwrap_or_else(|e| e.into_inner()).values() {
+        session.send(message);
+    }
+}
+
+/// Run `future` on behalf of `session`, if any: messages it sends go to that session
+pub async fn scope<F: Future>(session: Option<Arc<Session>>, future: F) -> F::Output {
+    match session {
+        Some(session) => CURRENT.scope(session, future).await,
+        None => future.await,
+    }
+}
+
+/// Run `f` on behalf of `session`, like `scope` for code that doesn't await
+pub fn sync_scope<R>(session: Arc<Session>, f: impl FnOnce() -> R) -> R {
+    CURRENT.sync_scope(session, f)
+}
+
+/// The session the current request belongs to
+pub fn current() -> Option<Arc<Session>> {
+    CURRENT.try_with(Arc::clone).ok()
+}
+
+/// Read or change the state of the current session, or `None` outside of a session, where the
+/// process-wide state applies
+pub fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> Option<R> {
+    current().map(|session| f(&mut session.state()))
+}
+
+/// Spawn a task that stays in the current session
+pub fn spawn<F>(future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(scope(current(), future))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_messages_go_to_the_current_session() {
+        let first = create();
+        let second = create();
+        assert_ne!(first.id, second.id);
+        let mut first_queue = first.take_receiver().unwrap();
+        assert!(first.take_receiver().is_none());
+
+        let sent = scope(Some(first.clone()), async {
+            current().unwrap().send(&json!({"method": "notifications/progress"}));
+            spawn(async { current().map(|session| session.id.clone()) }).await.unwrap()
+        })
+        .await;
+        assert_eq!(sent, Some(first.id.clone()));
+        assert_eq!(first_queue.recv().await.unwrap()["method"], json!("notifications/progress"));
+        assert!(current().is_none());
+
+        first.return_receiver(first_queue);
+        assert!(remove(&first.id) && remove(&second.id));
+        assert!(first.ended.is_cancelled());
+        assert!(get(&first.id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_state_is_kept_per_session() {
+        use crate::mcp::logging;
+        use crate::mcp::protocol;
+        let first = create();
+        let second = create();
+        scope(Some(first.clone()), async {
+            logging::set_level(LoggingLevel::Debug);
+            protocol::set_version("2024-11-05");
+        })
+        .await;
+        scope(Some(second.clone()), async {
+            assert_eq!(logging::level(), LoggingLevel::Info);
+            assert_eq!(protocol::version(), protocol::LATEST_VERSION);
+        })
+        .await;
+        assert_eq!(first.state().log_level, Some(LoggingLevel::Debug));
+        assert_eq!(first.state().protocol_version, Some("2024-11-05"));
+        assert!(remove(&first.id) && remove(&second.id));
+    }
+
+    #[test]
+    fn test_sessions_without_a_client_become_idle() {
+        let timeout = Duration::from_secs(60);
+        let later = Instant::now() + Duration::from_secs(61);
+        let session = create();
+        assert!(!session.is_idle(Instant::now(), timeout));
+        assert!(session.is_idle(later, timeout));
+        session.touch();
+        assert!(!session.is_idle(Instant::now(), timeout));
+
+        // an open stream keeps the session, however long it stays quiet
+        let receiver = session.take_receiver().unwrap();
+        assert!(!session.is_idle(later + timeout, timeout));
+        session.return_receiver(receiver);
+        assert!(remove(&session.id));
+    }
+}
@@ -0,0 +1,347 @@
+//! Resource subscriptions (`resources/subscribe`) and the file watcher that drives
+//! `notifications/resources/updated` and `notifications/resources/list_changed`.
+
+use crate::mcp::logging;
+use crate::mcp::paths::resolve_path;
+use crate::mcp::server_log;
+use crate::mcp::session;
+use crate::mcp::tools::collect_files;
+use crate::mcp::types::*;
+use crate::mcp::utilities::{get_allowed_directories, is_path_allowed, notify, validate_path_or_error, Access};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rpc_router::HandlerResult;
+use rpc_router::IntoHandlerError;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use url::Url;
+
+/// Changes are reported once the watched directories have been quiet this long, so that a save
+/// through a temporary file and a rename is seen as a single update of the target
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// files the stdio client subscribed to, by resolved path; sessions keep their own
+static SUBSCRIPTIONS: LazyLock<Mutex<HashSet<PathBuf>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+static WATCHING: Mutex<Option<Watching>> = Mutex::new(None);
+
+struct Watching {
+    watcher: RecommendedWatcher,
+    dirs: Vec<PathBuf>,
+    sender: mpsc::UnboundedSender<WatchMessage>,
+}
+
+enum WatchMessage {
+    /// something happened to this path
+    Changed(PathBuf),
+    /// the watched directories changed or events were lost; compare everything
+    Rescan,
+}
+
+fn invalid_params(message: String) -> rpc_router::HandlerError {
+    json!({"code": ErrorCode::InvalidParams as i32, "message": message}).into_handler_error()
+}
+
+fn file_path(uri: &Url) -> HandlerResult<PathBuf> {
+    uri.to_file_path().map_err(|_| invalid_params(format!("Invalid file URI: {}", uri)))
+}
+
+pub async fn resources_subscribe(request: SubscribeRequest) -> HandlerResult<EmptyResult> {
+    let path = file_path(&request.uri)?;
+    let resolved = validate_path_or_error(&path, Access::Read).map_err(invalid_params)?;
+    match session::current() {
+        Some(session) => session.state().subscriptions.insert(resolved.path),
+        None => SUBSCRIPTIONS.lock().unwrap_or_else(|e| e.into_inner()).insert(resolved.path),
+    };
+    Ok(EmptyResult {})
+}
+
+pub async fn resources_unsubscribe(request: UnsubscribeRequest) -> HandlerResult<EmptyResult> {
+    let path = file_path(&request.uri)?;
+    let path = resolve_path(&path).unwrap_or(path);
+    match session::current() {
+        Some(session) => session.state().subscriptions.remove(&path),
+        None => SUBSCRIPTIONS.lock().unwrap_or_else(|e| e.into_inner()).remove(&path),
+    };
+    Ok(EmptyResult {})
+}
+
+fn is_subscribed(path: &Path) -> bool {
+    SUBSCRIPTIONS.lock().unwrap_or_else(|e| e.into_inner()).contains(path)
+}
+
+/// The files a client can see as resources, kept up to date from watcher events so that only
+/// net changes to the list are reported
+struct ChangeTracker {
+    known: HashSet<PathBuf>,
+}
+
+impl ChangeTracker {
+    fn scan() -> ChangeTracker {
+        let known = watched_dirs()
+            .iter()
+            .flat_map(|dir| collect_files(dir))
+            .filter(|file| is_path_allowed(file, Access::Read))
+            .collect();
+        ChangeTracker { known }
+    }
+
+    /// Replace the known files with a fresh scan, returning whether they differ
+    fn rescan(&mut self) -> bool {
+        let scanned = ChangeTracker::scan();
+        let changed = scanned.known != self.known;
+        *self = scanned;
+        changed
+    }
+
+    /// Update the known files for the paths touched since the last call, returning whether the
+    /// list changed. A file that existed before and after (e.g. replaced by a rename) or that
+    /// came and went in between is not a change.
+    fn apply(&mut self, touched: &BTreeSet<PathBuf>) -> bool {
+        let mut changed = false;
+        for path in touched {
+            if path.is_dir() {
+                for file in collect_files(path) {
+                    if is_path_allowed(&file, Access::Read) {
+                        changed |= self.known.insert(file);
+                    }
+                }
+            } else if path.is_file() && is_path_allowed(path, Access::Read) {
+                changed |= self.known.insert(path.clone());
+            } else {
+                // gone, or a directory that was removed or renamed away
+                let before = self.known.len();
+                self.known.retain(|file| !file.starts_with(path));
+                changed |= self.known.len() != before;
+            }
+        }
+        changed
+    }
+}
+
+fn watched_dirs() -> Vec<PathBuf> {
+    get_allowed_directories()
+        .into_iter()
+        .filter_map(|dir| Path::new(&dir).canonicalize().ok())
+        .collect()
+}
+
+/// Start watching the allowed directories, sending notifications to the client
+pub fn start() {
+    start_with(notify);
+}
+
+fn start_with(emit: impl Fn(&str, Option<Value>) + Send + 'static) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let events = sender.clone();
+    let watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+        Ok(event) if event.need_rescan() => {
+            let _ = events.send(WatchMessage::Rescan);
+        }
+        Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+        Ok(event) => {
+            for path in event.paths {
+                let _ = events.send(WatchMessage::Changed(path));
+            }
+        }
+        Err(_) => {
+            let _ = events.send(WatchMessage::Rescan);
+        }
+    });
+    let watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            server_log::log_event("watch_failed", json!({ "error": e.to_string() }));
+            return;
+        }
+    };
+    *WATCHING.lock().unwrap_or_else(|e| e.into_inner()) = Some(Watching {
+        watcher,
+        dirs: Vec::new(),
+        sender,
+    });
+    sync_watches();
+    tokio::spawn(async move {
+        // the first scan can take a while in a large tree; events meanwhile wait in the channel
+        let Ok(tracker) = tokio::task::spawn_blocking(ChangeTracker::scan).await else {
+            return;
+        };
+        run(receiver, tracker, emit).await;
+    });
+}
+
+/// Watch the current allowed directories, e.g. after the client's roots changed
+pub fn sync_watches() {
+    let mut watching = WATCHING.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(watching) = watching.as_mut() else {
+        return;
+    };
+    let dirs = watched_dirs();
+    if dirs == watching.dirs {
+        return;
+    }
+    for dir in &watching.dirs {
+        let _ = watching.watcher.unwatch(dir);
+    }
+    for dir in &dirs {
+        if let Err(e) = watching.watcher.watch(dir, RecursiveMode::Recursive) {
+            logging::warning("resources", format!("Cannot watch {} for changes: {}", dir.display(), e));
+            server_log::log_event("watch_failed", json!({ "path": dir, "error": e.to_string() }));
+        }
+    }
+    watching.dirs = dirs;
+    let _ = watching.sender.send(WatchMessage::Rescan);
+}
+
+async fn run(
+    mut receiver: mpsc::UnboundedReceiver<WatchMessage>,
+    mut tracker: ChangeTracker,
+    emit: impl Fn(&str, Option<Value>),
+) {
+    while let Some(message) = receiver.recv().await {
+        let mut touched = BTreeSet::new();
+        let mut rescan = false;
+        let mut next = Some(message);
+        while let Some(message) = next {
+            match message {
+                WatchMessage::Changed(path) => {
+                    touched.insert(path);
+                }
+                WatchMessage::Rescan => rescan = true,
+            }
+            next = tokio::time::timeout(DEBOUNCE, receiver.recv()).await.ok().flatten();
+        }
+
+        let list_changed = if rescan { tracker.rescan() } else { tracker.apply(&touched) };
+        if list_changed {
+            emit("notifications/resources/list_changed", None);
+        }
+        let sessions = session::all();
+        for path in &touched {
+            let Ok(uri) = Url::from_file_path(path) else { continue };
+            let params = serde_json::to_value(ResourceUpdatedNotification { uri }).ok();
+            // only to the clients that subscribed
+            if is_subscribed(path) {
+                emit("notifications/resources/updated", params.clone());
+            }
+            for session in sessions.iter().filter(|session| session.state().subscriptions.contains(path)) {
+                session::sync_scope(session.clone(), || emit("notifications/resources/updated", params.clone()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::TEST_ENV_LOCK;
+    use std::env;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_change_tracker_reports_net_list_changes() {
+        let _env_guard = TEST_ENV_LOCK.lock().await;
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &root);
+        fs::write(root.join("notes.txt"), "one").unwrap();
+        let mut tracker = ChangeTracker::scan();
+        let touched = |paths: &[&str]| paths.iter().map(|path| root.join(path)).collect::<BTreeSet<_>>();
+
+        // save through a temporary file renamed over the original
+        fs::write(root.join(".notes.txt.swp"), "two").unwrap();
+        fs::rename(root.join(".notes.txt.swp"), root.join("notes.txt")).unwrap();
+        assert!(!tracker.apply(&touched(&["notes.txt", ".notes.txt.swp"])));
+
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        assert!(tracker.apply(&touched(&["src"])));
+        assert!(tracker.known.contains(&root.join("src/lib.rs")));
+
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        assert!(!tracker.apply(&touched(&[".env"])));
+
+        fs::remove_dir_all(root.join("src")).unwrap();
+        assert!(tracker.apply(&touched(&["src"])));
+        assert!(!tracker.rescan());
+
+        env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+    }
+
+    #[tokio::test]
+    async fn test_watcher_notifies_subscribers() {
+        let _env_guard = TEST_ENV_LOCK.lock().await;
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &root);
+        let notes = root.join("notes.txt");
+        fs::write(&notes, "one").unwrap();
+        let uri = Url::from_file_path(&notes).unwrap();
+        resources_subscribe(SubscribeRequest { uri: uri.clone() }).await.unwrap();
+        assert!(resources_subscribe(SubscribeRequest { uri: Url::parse("file:///etc/hostname").unwrap() }).await.is_err());
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        start_with(move |method, params| {
+            let _ = sender.send((method.to_string(), params));
+        });
+
+        fs::write(&notes, "two").unwrap();
+        let (method, params) = tokio::time::timeout(Duration::from_secs(10), receiver.recv()).await.unwrap().unwrap();
+        assert_eq!(method, "notifications/resources/updated");
+        assert_eq!(params, Some(json!({ "uri": uri })));
+
+        fs::write(root.join("new.txt"), "").unwrap();
+        let (method, params) = tokio::time::timeout(Duration::from_secs(10), receiver.recv()).await.unwrap().unwrap();
+        assert_eq!(method, "notifications/resources/list_changed");
+        assert_eq!(params, None);
+
+        resources_unsubscribe(UnsubscribeRequest { uri }).await.unwrap();
+        assert!(!is_subscribed(&notes));
+        *WATCHING.lock().unwrap() = None;
+        env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+    }
+    #[tokio::test]
+    async fn test_refused_subscriptions_and_other_files_are_not_notified() {
+        let _env_guard = TEST_ENV_LOCK.lock().await;
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &root);
+        let notes = root.join("notes.txt");
+        let other = root.join("other.txt");
+        fs::write(&notes, "one").unwrap();
+        fs::write(&other, "one").unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        let subscribe = |uri: Url| resources_subscribe(SubscribeRequest { uri });
+        assert!(subscribe(Url::from_file_path(root.join(".env")).unwrap()).await.is_err());
+        assert!(subscribe(Url::parse("https://example.com/notes.txt").unwrap()).await.is_err());
+        assert!(!is_subscribed(&root.join(".env")));
+        let uri = Url::from_file_path(&notes).unwrap();
+        subscribe(uri.clone()).await.unwrap();
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        start_with(move |method, params| {
+            let _ = sender.send((method.to_string(), params));
+        });
+
+        // only the subscribed file is reported, and the list of files is unchanged
+        fs::write(&other, "two").unwrap();
+        fs::write(root.join(".env"), "SECRET=2").unwrap();
+        fs::write(&notes, "two").unwrap();
+        let (method, params) = tokio::time::timeout(Duration::from_secs(10), receiver.recv()).await.unwrap().unwrap();
+        assert_eq!(method, "notifications/resources/updated");
+        assert_eq!(params, Some(json!({ "uri": uri })));
+        assert!(tokio::time::timeout(DEBOUNCE * 3, receiver.recv()).await.is_err());
+
+        resources_unsubscribe(UnsubscribeRequest { uri }).await.unwrap();
+        *WATCHING.lock().unwrap() = None;
+        env::remove_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES");
+    }
+}
@@ -0,0 +1,126 @@
+//! Tool declarations. Each tool is declared once, and its schemas are derived from the types its
+//! handler takes and returns, so `tools/list` can't drift from what the handler accepts.
+
+use crate::mcp::types::Tool;
+use crate::mcp::types::ToolAnnotations;
+use rpc_router::RouterBuilder;
+use schemars::generate::SchemaSettings;
+use schemars::JsonSchema;
+use serde_json::Value;
+
+pub struct ToolDefinition {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    pub hints: ToolHints,
+    /// JSON Schema of the arguments, derived from the request type
+    pub input_schema: fn() -> Value,
+    /// JSON Schema of `structuredContent`, for tools that return it
+    pub output_schema: Option<fn() -> Value>,
+    /// Add the handler to the router under the tool's name
+    pub register: fn(RouterBuilder, &'static str) -> RouterBuilder,
+}
+
+/// What a tool may do to the filesystem, for clients deciding which tools to run without asking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToolHints {
+    pub read_only: bool,
+    pub destructive: bool,
+    pub idempotent: bool,
+}
+
+impl ToolHints {
+    pub const READ_ONLY: ToolHints = ToolHints { read_only: true, destructive: false, idempotent: true };
+    /// changes state but never loses data, and repeating the call changes nothing
+    pub const ADDITIVE: ToolHints = ToolHints { read_only: false, destructive: false, idempotent: true };
+    /// replaces data, with the same result when repeated
+    pub const OVERWRITE: ToolHints = ToolHints { read_only: false, destructive: true, idempotent: true };
+    pub const DESTRUCTIVE: ToolHints = ToolHints { read_only: false, destructive: true, idempotent: false };
+}
+
+impl ToolDefinition {
+    /// The `tools/list` entry
+    pub fn tool(&self) -> Tool {
+        Tool {
+            name: self.name.to_string(),
+            title: Some(self.title.to_string()),
+            description: Some(self.description.to_string()),
+            input_schema: (self.input_schema)(),
+            output_schema: self.output_schema.map(|schema| schema()),
+            annotations: Some(ToolAnnotations {
+                read_only_hint: Some(self.hints.read_only),
+                destructive_hint: Some(self.hints.destructive),
+                idempotent_hint: Some(self.hints.idempotent),
+                // no tool reaches outside the local filesystem
+                open_world_hint: Some(false),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// The JSON Schema of `T`, self-contained and without the `$schema` and `title` noise
+pub fn schema_for<T: JsonSchema>() -> Value {
+    let generator = SchemaSettings::draft2020_12()
+        .with(|settings| {
+            settings.meta_schema = None;
+            settings.inline_subschemas = true;
+        })
+        .into_generator();
+    let mut schema = generator.into_root_schema_for::<T>().to_value();
+    if let Some(schema) = schema.as_object_mut() {
+        schema.remove("title");
+        // clients expect `properties` even when there are no arguments
+        schema.entry("properties").or_insert_with(|| Value::Object(Default::default()));
+    }
+    remove_null(&mut schema);
+    schema
+}
+
+/// Optional fields are left out rather than sent as null, so drop `null` from their types
+fn remove_null(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => {
+            if let Some(Value::Array(types)) = object.get_mut("type") {
+                types.retain(|type_name| type_name != "null");
+                if let [type_name] = types.as_slice() {
+                    let type_name = type_name.clone();
+                    object.insert("type".to_string(), type_name);
+                }
+            }
+            if object.get("default") == Some(&Value::Null) {
+                object.remove("default");
+            }
+            object.values_mut().for_each(remove_null);
+        }
+        Value::Array(items) => items.iter_mut().for_each(remove_null),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize, JsonSchema)]
+    #[allow(dead_code)]
+    struct ExampleRequest {
+        /// Path to the file
+        path: String,
+        #[serde(default)]
+        limit: Option<usize>,
+    }
+
+    #[test]
+    fn test_schema_lists_fields_with_descriptions_and_required() {
+        let schema = schema_for::<ExampleRequest>();
+        assert_eq!(schema["type"], json!("object"));
+        assert_eq!(schema["properties"]["path"], json!({"type": "string", "description": "Path to the file"}));
+        assert_eq!(schema["properties"]["limit"]["type"], json!("integer"));
+        assert!(schema["properties"]["limit"].get("default").is_none());
+        assert_eq!(schema["required"], json!(["path"]));
+        assert!(schema.get("$schema").is_none() && schema.get("title").is_none());
+    }
+}
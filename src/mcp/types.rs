@@ -73,6 +73,8 @@ pub struct RootCapabilities {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Implementation {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
     pub version: String,
 }
 
@@ -118,17 +120,24 @@ pub struct ReadResourceRequest {
     pub meta: Option<MetaParams>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct ReadResourceResult {
-    pub contents: Vec<TextResourceContents>,
+#[derive(Debug, Deserialize, Serialize, RpcParams)]
+pub struct SubscribeRequest {
+    pub uri: Url,
+}
+
+#[derive(Debug, Deserialize, Serialize, RpcParams)]
+pub struct UnsubscribeRequest {
+    pub uri: Url,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-pub struct TextResourceContents {
+pub struct ResourceUpdatedNotification {
     pub uri: Url,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub mime_type: Option<String>,
-    pub text: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReadResourceResult {
+    pub contents: Vec<ResourceContent>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -137,10 +146,36 @@ pub struct ResourceContent {
     pub uri: Url, // The URI of the resource
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mime_type: Option<String>, // Optional MIME type
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub text: Option<String>, // For text resources
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub blob: Option<String>, // For binary resources (base64 encoded)
 }
 
+#[derive(Debug, Deserialize, Serialize, RpcParams)]
+pub struct ListResourceTemplatesRequest {
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResourceTemplatesResult {
+    pub resource_templates: Vec<ResourceTemplate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceTemplate {
+    pub uri_template: String, // RFC 6570 URI template
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
 // --------- prompt -------
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Prompt {
@@ -202,33 +237,36 @@ pub struct PromptMessageContent {
 
 // --------- tool -------
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Tool {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
-    pub input_schema: ToolInputSchema,
-}
-
-#[derive(Deserialize, Serialize)]
-pub struct ToolInputSchema {
-    #[serde(rename = "type")]
-    pub type_name: String,
-    pub properties: HashMap<String, ToolInputSchemaProperty>,
-    pub required: Vec<String>,
+    /// JSON Schema of the arguments
+    pub input_schema: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<ToolAnnotations>,
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct ToolInputSchemaProperty {
+/// Hints about a tool's behavior, for clients deciding e.g. whether to ask before calling it
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename = "type")]
-    pub type_name: Option<String>,
+    pub title: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename = "enum")]
-    pub enum_values: Option<Vec<String>>,
+    pub read_only_hint: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub description: Option<String>,
+    pub destructive_hint: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotent_hint: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_world_hint: Option<bool>,
 }
 
 #[derive(Deserialize, Serialize, RpcParams)]
@@ -245,11 +283,14 @@ pub struct ToolCallRequestParams {
     pub arguments: Option<Value>,
 }
 
-#[derive(Deserialize, Serialize, RpcParams)]
+#[derive(Deserialize, Serialize, RpcParams, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct CallToolResult {
     pub content: Vec<CallToolResultContent>,
     pub is_error: bool,
+    /// the result as JSON matching the tool's `outputSchema`, next to the text content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured_content: Option<Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -261,6 +302,13 @@ pub enum CallToolResultContent {
     Image { data: String, mime_type: String },
     #[serde(rename = "resource")]
     Resource { resource: ResourceContent },
+    #[serde(rename = "resource_link", rename_all = "camelCase")]
+    ResourceLink {
+        uri: Url,
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mime_type: Option<String>,
+    },
 }
 
 #[derive(Debug, Deserialize, Serialize, RpcParams)]
@@ -286,22 +334,27 @@ pub struct PingRequest {}
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelledNotification {
-    pub request_id: String,
+    pub request_id: Value,
     pub reason: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MetaParams {
-    pub progress_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress_token: Option<Value>, // string or number
 }
 
+/// Params of `notifications/progress`
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Progress {
-    pub progress_token: String,
-    pub progress: i32,
-    pub total: i32,
+    pub progress_token: Value,
+    pub progress: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, RpcParams)]
@@ -312,16 +365,30 @@ pub struct SetLevelRequest {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LoggingResponse {}
 
-#[derive(Debug, Deserialize, Serialize, RpcParams)]
+/// Syslog severities, from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoggingLevel {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+    Alert,
+    Emergency,
+}
+
+/// Params of `notifications/message`
+#[derive(Debug, Deserialize, Serialize)]
 pub struct LoggingMessageNotification {
-    pub level: String,
-    pub logger: String,
+    pub level: LoggingLevel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logger: Option<String>,
     pub data: Value,
 }
 
-#[derive(Debug, Deserialize, Serialize, RpcParams)]
-pub struct ListRootsRequest {}
-
+/// result of the `roots/list` request the server sends to the client
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ListRootsResult {
     pub roots: Vec<Root>,
@@ -329,12 +396,12 @@ pub struct ListRootsResult {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Root {
-    pub name: String,
-    pub url: String,
+    pub uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
 pub enum ErrorCode {
     // MCP SDK error codes
     ConnectionClosed = -1,
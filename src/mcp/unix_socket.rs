@@ -0,0 +1,238 @@
+//! MCP over a Unix domain socket, for local daemons and editor integrations that share one
+//! server. Clients exchange JSON-RPC messages one per line, as over stdio, and every connection is
+//! a session of its own. Who can connect is decided by the socket file's permissions.
+
+use crate::mcp::output;
+use crate::mcp::rpc;
+use crate::mcp::rpc::Dispatcher;
+use crate::mcp::server_log;
+use crate::mcp::session;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::fs::DirBuilder;
+use std::fs::Permissions;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::UnixListener;
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+/// Serve MCP on the socket at `path` until the process exits
+pub async fn serve(path: &Path, mode: u32, dispatcher: Dispatcher) -> Result<(), String> {
+    let listener = bind(path, mode)?;
+    output::sessions_only();
+    server_log::log_event("socket_listening", json!({ "path": path, "mode": format!("{:o}", mode) }));
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_connection(stream, dispatcher.clone()));
+            }
+            Err(e) => server_log::log_event("socket_accept_failed", json!({ "error": e.to_string() })),
+        }
+    }
+}
+
+/// Listen on `path`, replacing a stale socket left by a server that didn't shut down cleanly.
+/// The socket is created in a private directory and moved into place once its permissions are
+/// set, so nobody can connect in between.
+fn bind(path: &Path, mode: u32) -> Result<UnixListener, String> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(format!("{} is in use by another server", path.display()));
+            }
+        }
+        Ok(_) => return Err(format!("{} exists and is not a socket", path.display())),
+        Err(_) => {}
+    }
+    let private_dir = path.with_file_name(format!(".rs_filesystem.{}", std::process::id()));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)
+        .map_err(|e| format!("Failed to create {}: {}", private_dir.display(), e))?;
+    let temporary = private_dir.join("socket");
+    let bound = UnixListener::bind(&temporary)
+        .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))
+        .and_then(|listener| {
+            fs::set_permissions(&temporary, Permissions::from_mode(mode))
+                .and_then(|_| fs::rename(&temporary, path))
+                .map_err(|e| format!("Failed to set up {}: {}", path.display(), e))?;
+            Ok(listener)
+        });
+    let _ = fs::remove_file(&temporary);
+    let _ = fs::remove_dir(&private_dir);
+    bound
+}
+
+/// Handle one client until it disconnects. Like stdio, `initialize` is handled before the next
+/// line is read, and other requests run concurrently.
+async fn serve_connection(stream: UnixStream, dispatcher: Dispatcher) {
+    let session = session::create();
+    server_log::log_event("socket_connected", json!({ "session": session.id }));
+    let (reader, writer) = stream.into_split();
+    let Some(outbound) = session.take_receiver() else {
+        return;
+    };
+    // Responses have a queue of their own: the session's queue drops messages when it is full,
+    // which is fine for notifications but would leave the client waiting for a response forever
+    let (responses, response_queue) = mpsc::unbounded_channel();
+    let writing = tokio::spawn(write_messages(writer, response_queue, outbound, session.ended.clone()));
+
+    let mut lines = BufReader::new(reader).split(b'\n');
+    let mut requests = JoinSet::new();
+    while let Ok(Some(line)) = lines.next_segment().await {
+        while requests.try_join_next().is_some() {}
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let Ok(message) = serde_json::from_slice::<Value>(&line) else {
+            respond(&responses, &rpc::parse_error());
+            continue;
+        };
+        server_log::log_received(&message);
+        let is_initialize = message.get("method").and_then(Value::as_str) == Some("initialize");
+        let response = session::sync_scope(session.clone(), || dispatcher.dispatch(message));
+        if is_initialize {
+            if let Some(response) = response.await {
+                respond(&responses, &response);
+            }
+            continue;
+        }
+        let responses = responses.clone();
+        requests.spawn(async move {
+            if let Some(response) = response.await {
+                respond(&responses, &response);
+            }
+        });
+    }
+
+    // the client hung up: finish what it asked for, then end the session
+    while requests.join_next().await.is_some() {}
+    session::remove(&session.id);
+    let _ = writing.await;
+    server_log::log_event("socket_disconnected", json!({ "session": session.id }));
+}
+
+fn respond(responses: &mpsc::UnboundedSender<Value>, response: &Value) {
+    server_log::log_message(response);
+    let _ = responses.send(response.clone());
+}
+
+/// Write responses and the session's other messages to the connection, one per line, until
+/// the session ends and both queues are drained
+async fn write_messages(
+    mut writer: OwnedWriteHalf,
+    mut responses: mpsc::UnboundedReceiver<Value>,
+    mut outbound: mpsc::Receiver<Value>,
+    ended: CancellationToken,
+) {
+    loop {
+        let message = tokio::select! {
+            biased;
+            Some(response) = responses.recv() => Some(response),
+            message = outbound.recv() => message,
+            _ = ended.cancelled() => None,
+        };
+        let Some(message) = message else {
+            break;
+        };
+        let line = format!("{}\n", message);
+        if writer.write_all(line.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_bind_sets_permissions_and_replaces_stale_sockets() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("mcp.sock");
+
+        let listener = bind(&path, 0o600).unwrap();
+        let metadata = fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert!(bind(&path, 0o600).unwrap_err().contains("in use"));
+
+        // a socket nobody listens on any more is replaced
+        drop(listener);
+        let _listener = bind(&path, 0o660).unwrap();
+        assert_eq!(fs::symlink_metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        let file = temp_dir.path().join("notes.txt");
+        fs::write(&file, "").unwrap();
+        assert!(bind(&file, 0o600).unwrap_err().contains("not a socket"));
+        assert!(bind(&temp_dir.path().join("missing/mcp.sock"), 0o600).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bad_messages_are_answered_and_the_session_ends_on_disconnect() {
+        use tokio::io::AsyncReadExt;
+
+        let _env_guard = crate::mcp::TEST_ENV_LOCK.lock().await;
+        let temp_dir = TempDir::new().unwrap();
+        server_log::set_log_path(Some(temp_dir.path().join("server.logs.jsonl")));
+        let dispatcher = Dispatcher::new(rpc_router::RouterBuilder::default().build(), 1);
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let connection = tokio::spawn(serve_connection(server, dispatcher));
+
+        client
+            .write_all(b"{not json\n\n{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"no/such/method\"}\n")
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        let mut written = String::new();
+        client.read_to_string(&mut written).await.unwrap();
+        connection.await.unwrap();
+
+        let responses: Vec<Value> = written.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(responses.len(), 2, "{}", written);
+        assert_eq!(responses[0], rpc::parse_error());
+        assert_eq!(responses[1]["id"], json!(1));
+        assert_eq!(responses[1]["error"]["code"], json!(-32601));
+        let log = fs::read_to_string(temp_dir.path().join("server.logs.jsonl")).unwrap();
+        let disconnected = log.lines().find(|line| line.contains("socket_disconnected")).unwrap();
+        let disconnected: Value = serde_json::from_str(disconnected).unwrap();
+        assert!(session::get(disconnected["session"].as_str().unwrap()).is_none());
+
+        server_log::set_log_path(None);
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_not_dropped_when_notifications_back_up() {
+        use tokio::io::AsyncReadExt;
+
+        let session = session::create();
+        let outbound = session.take_receiver().unwrap();
+        for _ in 0..2000 {
+            session.send(&json!({"jsonrpc": "2.0", "method": "notifications/message"}));
+        }
+        let (responses, response_queue) = mpsc::unbounded_channel();
+        responses.send(json!({"jsonrpc": "2.0", "id": 1, "result": {}})).unwrap();
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let (_, writer) = server.into_split();
+        session::remove(&session.id);
+        let writing = tokio::spawn(write_messages(writer, response_queue, outbound, session.ended.clone()));
+
+        let mut written = String::new();
+        client.read_to_string(&mut written).await.unwrap();
+        writing.await.unwrap();
+        assert_eq!(written.lines().filter(|line| line.contains("\"id\":1")).count(), 1);
+    }
+}
@@ -1,13 +1,48 @@
+use crate::mcp::audit;
+use crate::mcp::cancellation;
+use crate::mcp::config;
+use crate::mcp::config::AccessMode;
+use crate::mcp::config::RootPolicy;
+use crate::mcp::output;
+use crate::mcp::paths;
+use crate::mcp::paths::OpenMode;
+use crate::mcp::protocol;
+use crate::mcp::protocol::Feature;
+use crate::mcp::roots;
+use crate::mcp::server_log;
 use crate::mcp::types::*;
-use crate::mcp::PROTOCOL_VERSION;
 use crate::mcp::SERVER_NAME;
+use crate::mcp::SERVER_TITLE;
 use crate::mcp::SERVER_VERSION;
 use rpc_router::HandlerResult;
 use serde_json::json;
 use serde_json::Value;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 
+/// All allowed directories: roots from the config file followed by those from the env variable,
+/// narrowed to the client's roots when it shares them
 pub fn get_allowed_directories() -> Vec<String> {
+    let mut dirs: Vec<String> = config::current()
+        .roots
+        .iter()
+        .map(|root| root.path.to_string_lossy().into_owned())
+        .collect();
+    dirs.extend(get_env_allowed_directories());
+    roots::intersect(dirs)
+}
+
+/// The directories in the env variable, unless the server was built not to use them
+fn get_env_allowed_directories() -> Vec<String> {
+    if !config::env_roots() {
+        return Vec::new();
+    }
     std::env::var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES")
         .unwrap_or_default()
         .split(':')
@@ -16,22 +51,44 @@ pub fn get_allowed_directories() -> Vec<String> {
         .collect()
 }
 
+/// Roots from the env variable grant full access, subject to the config file's global deny list
+fn get_allowed_roots() -> Vec<RootPolicy> {
+    let policies = config::current();
+    let mut roots = policies.roots.clone();
+    roots.extend(get_env_allowed_directories().into_iter().map(|dir| RootPolicy {
+        path: PathBuf::from(dir),
+        mode: AccessMode::ReadWrite,
+        deny: policies.deny.clone(),
+        max_file_size: policies.max_file_size,
+    }));
+    roots
+}
+
 /// handler for `initialize` request from client
-pub async fn initialize(_request: InitializeRequest) -> HandlerResult<InitializeResult> {
+pub async fn initialize(request: InitializeRequest) -> HandlerResult<InitializeResult> {
+    audit::set_client_info(request.client_info);
+    roots::set_client_supports_roots(request.capabilities.roots.is_some());
+    roots::set_client_roots(None);
+    let version = protocol::negotiate(&request.protocol_version);
+    protocol::set_version(version);
     let result = InitializeResult {
-        protocol_version: PROTOCOL_VERSION.to_string(),
+        protocol_version: version.to_string(),
         server_info: Implementation {
             name: SERVER_NAME.to_string(),
+            title: protocol::supports(Feature::Titles).then(|| SERVER_TITLE.to_string()),
             version: SERVER_VERSION.to_string(),
         },
         capabilities: ServerCapabilities {
             experimental: None,
             prompts: Some(PromptCapabilities::default()),
-            resources: None,
+            resources: Some(ResourceCapabilities {
+                subscribe: Some(true),
+                list_changed: Some(true),
+            }),
             tools: Some(json!({})),
             roots: None,
             sampling: None,
-            logging: None,
+            logging: Some(json!({})),
         },
         instructions: None,
     };
@@ -47,109 +104,295 @@ pub fn graceful_shutdown() {
 pub fn notifications_initialized() {}
 
 /// handler for `notifications/cancelled` from client
-pub fn notifications_cancelled(_params: CancelledNotification) {
-    // cancel request
+pub fn notifications_cancelled(params: CancelledNotification) {
+    if cancellation::cancel(&params.request_id) {
+        server_log::log_event("request_cancelled", json!({
+            "request_id": params.request_id,
+            "reason": params.reason,
+        }));
+    }
 }
 
 pub async fn ping() -> HandlerResult<EmptyResult> {
     Ok(EmptyResult {})
 }
 
-pub async fn logging_set_level(_request: SetLevelRequest) -> HandlerResult<LoggingResponse> {
-    Ok(LoggingResponse {})
-}
-
-pub async fn roots_list(_request: Option<ListRootsRequest>) -> HandlerResult<ListRootsResult> {
-    let response = ListRootsResult {
-        roots: vec![Root {
-            name: "my project".to_string(),
-            url: "file:///home/user/projects/my-project".to_string(),
-        }],
-    };
-    Ok(response)
-}
 
 /// send notification to client
-#[allow(dead_code)]
 pub fn notify(method: &str, params: Option<Value>) {
-    let notification = json!({
+    let mut notification = json!({
         "jsonrpc": "2.0",
         "method": method,
-        "params": params,
     });
-    println!("{}", serde_json::to_string(&notification).unwrap());
-}
-
-pub fn is_path_allowed(path: &Path) -> bool {
-    let allowed_dirs = get_allowed_directories();
-    if allowed_dirs.is_empty() {
-        return false; // If no directories are explicitly allowed, deny all access
-    }
-
-    // Get all parent directories of the path, including itself
-    let mut check_path = path.to_path_buf();
-    loop {
-        // Try to canonicalize the current path if it exists
-        let canonical_check = if check_path.exists() {
-            match check_path.canonicalize() {
-                Ok(p) => p,
-                Err(_) => check_path.clone(),
-            }
-        } else {
-            check_path.clone()
-        };
-
-        // Check if this path or parent is allowed
-        for allowed_dir in &allowed_dirs {
-            let allowed_path = Path::new(allowed_dir);
-            let canonical_allowed = if allowed_path.exists() {
-                match allowed_path.canonicalize() {
-                    Ok(p) => p,
-                    Err(_) => continue,
-                }
-            } else {
-                continue;
-            };
-
-            if canonical_check.starts_with(&canonical_allowed) {
-                return true;
-            }
+    if let Some(params) = params {
+        notification["params"] = params;
+    }
+    output::send(&notification);
+}
+
+/// The kind of operation a path is validated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    /// create or modify
+    Write,
+    /// delete, or move away from this path
+    Delete,
+}
+
+impl Access {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Access::Read => "reading",
+            Access::Write => "writing",
+            Access::Delete => "deleting",
         }
+    }
+}
+
+/// The root governing a path, with the path resolved against it
+struct RootMatch {
+    root: RootPolicy,
+    canonical_root: PathBuf,
+    /// the path relative to the root, used for deny patterns
+    relative: PathBuf,
+    resolved: PathBuf,
+}
+
+/// A path that passed validation. Files are opened through it relative to a handle on the
+/// root directory, so a symlink planted after validation cannot redirect the open.
+#[derive(Debug, Clone)]
+pub struct ResolvedPath {
+    /// the absolute path with `..` and symlinks resolved
+    pub path: PathBuf,
+    root: PathBuf,
+    relative: PathBuf,
+}
+
+impl ResolvedPath {
+    pub fn open(&self, mode: OpenMode) -> io::Result<File> {
+        paths::open_beneath(&self.root, &self.relative, mode)
+    }
+
+    pub fn read_to_string(&self) -> io::Result<String> {
+        let mut content = String::new();
+        self.open(OpenMode::Read)?.read_to_string(&mut content)?;
+        audit::record_read(&self.path, content.as_bytes());
+        Ok(content)
+    }
+
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        self.open(OpenMode::Read)?.read_to_end(&mut content)?;
+        audit::record_read(&self.path, &content);
+        Ok(content)
+    }
+
+    /// Metadata of the file itself, not of a symlink's target
+    pub fn metadata(&self) -> io::Result<fs::Metadata> {
+        paths::metadata_beneath(&self.root, &self.relative)
+    }
+
+    /// Names of the entries in this directory
+    pub fn read_dir(&self) -> io::Result<Vec<OsString>> {
+        paths::read_dir_beneath(&self.root, &self.relative)
+    }
+
+    pub fn create_dir_all(&self) -> io::Result<()> {
+        paths::create_dir_all_beneath(&self.root, &self.relative)
+    }
+
+    pub fn rename_to(&self, target: &ResolvedPath) -> io::Result<()> {
+        paths::rename_beneath(&self.root, &self.relative, &target.root, &target.relative)
+    }
+
+    pub fn remove_file(&self) -> io::Result<()> {
+        paths::remove_beneath(&self.root, &self.relative, false)
+    }
+
+    pub fn remove_dir(&self) -> io::Result<()> {
+        paths::remove_beneath(&self.root, &self.relative, true)
+    }
 
-        // Move up to parent directory
-        match check_path.parent() {
-            Some(parent) => check_path = parent.to_path_buf(),
-            None => break,
+    /// The directory containing this path, unless this is the root itself. It has not been
+    /// validated on its own, so it is only for creating or cleaning up what leads to this path.
+    pub(crate) fn parent(&self) -> Option<ResolvedPath> {
+        let relative = self.relative.parent()?;
+        Some(ResolvedPath {
+            path: self.root.join(relative),
+            root: self.root.clone(),
+            relative: relative.to_path_buf(),
+        })
+    }
+
+    /// The entry `name` of this directory, which has not been validated on its own
+    pub(crate) fn join(&self, name: impl AsRef<Path>) -> ResolvedPath {
+        ResolvedPath {
+            path: self.path.join(&name),
+            root: self.root.clone(),
+            relative: self.relative.join(&name),
+        }
+    }
+
+    /// A sibling named `file_name` beneath the same root, for files staged next to this one
+    pub(crate) fn with_file_name(&self, file_name: &str) -> ResolvedPath {
+        ResolvedPath {
+            path: self.path.with_file_name(file_name),
+            root: self.root.clone(),
+            relative: self.relative.with_file_name(file_name),
         }
     }
 
-    false
+    pub fn write(&self, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        let contents = contents.as_ref();
+        // The previous content is only needed for the audit log's hash
+        let before = audit::is_active()
+            .then(|| {
+                let mut before = Vec::new();
+                self.open(OpenMode::Read).and_then(|mut file| file.read_to_end(&mut before)).ok()?;
+                Some(before)
+            })
+            .flatten();
+        self.open(OpenMode::Write)?.write_all(contents)?;
+        audit::record_write(&self.path, before.as_deref(), Some(contents));
+        Ok(())
+    }
 }
 
-pub fn validate_path_or_error(path: &Path) -> Result<(), String> {
-    if !is_path_allowed(path) {
-        Err(format!(
+/// Find the most specific allowed root containing `path`, after resolving `..` and symlinks
+fn find_root(path: &Path) -> Result<Option<RootMatch>, String> {
+    let allowed_roots = get_allowed_roots();
+    if allowed_roots.is_empty() {
+        return Ok(None); // If no directories are explicitly allowed, deny all access
+    }
+    let resolved = paths::resolve_path(path)
+        .map_err(|e| format!("Access denied: cannot resolve {}: {}", path.display(), e))?;
+
+    let best = allowed_roots
+        .iter()
+        .filter_map(|root| root.path.canonicalize().ok().map(|canonical| (canonical, root)))
+        .filter(|(canonical_root, _)| resolved.starts_with(canonical_root))
+        .max_by_key(|(canonical_root, _)| canonical_root.components().count());
+    Ok(best.map(|(canonical_root, root)| RootMatch {
+        root: root.clone(),
+        relative: resolved
+            .strip_prefix(&canonical_root)
+            .unwrap_or(Path::new(""))
+            .to_path_buf(),
+        canonical_root,
+        resolved,
+    }))
+}
+
+/// Check that `path` is inside an allowed root, that the root's mode permits `access`,
+/// and that no deny pattern matches.
+fn check_path_access(path: &Path, access: Access) -> Result<RootMatch, String> {
+    if access != Access::Read && config::is_read_only() {
+        return Err(format!(
+            "Access denied: the server is running in read-only mode, {} {} is not permitted",
+            access.as_str(),
+            path.display()
+        ));
+    }
+    let Some(found) = find_root(path)?.filter(|found| roots::is_within_client_roots(&found.resolved)) else {
+        return Err(format!(
             "Access denied: {} is not within allowed directories. Use the allowed_directories resource to view permitted locations.",
             path.display()
-        ))
-    } else {
-        Ok(())
+        ));
+    };
+    if !found.root.mode.permits(access) {
+        return Err(format!(
+            "Access denied: {} is in a {} directory ({}), {} is not permitted",
+            path.display(),
+            found.root.mode.as_str(),
+            found.root.path.display(),
+            access.as_str()
+        ));
+    }
+    if found.root.deny.is_match(&found.relative) || found.root.deny.is_match(&found.resolved) {
+        return Err(format!(
+            "Access denied: {} matches a deny pattern",
+            path.display()
+        ));
+    }
+    let sensitive = &config::current().sensitive;
+    if sensitive.is_match(&found.relative) || sensitive.is_match(&found.resolved) {
+        server_log::log_event("sensitive_file_blocked", json!({
+            "path": found.resolved,
+            "access": access.as_str(),
+        }));
+        return Err(format!(
+            "Access denied: {} may contain credentials or keys and is protected",
+            path.display()
+        ));
     }
+    Ok(found)
 }
 
-// For operations that involve two paths (like move/rename)
-pub fn validate_paths_or_error(source: &Path, target: &Path) -> Result<(), String> {
-    if !is_path_allowed(source) {
-        Err(format!(
-            "Access denied: source path {} is not within allowed directories",
-            source.display()
-        ))
-    } else if !is_path_allowed(target) {
-        Err(format!(
-            "Access denied: target path {} is not within allowed directories",
-            target.display()
-        ))
-    } else {
-        Ok(())
+pub fn is_path_allowed(path: &Path, access: Access) -> bool {
+    check_path_access(path, access).is_ok()
+}
+
+impl From<RootMatch> for ResolvedPath {
+    fn from(found: RootMatch) -> ResolvedPath {
+        ResolvedPath {
+            path: found.resolved,
+            root: found.canonical_root,
+            relative: found.relative,
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Audit a refused access under the path it resolves to, or as requested if it can't be resolved
+fn record_denied(path: &Path, access: Access) {
+    let resolved = paths::resolve_path(path).unwrap_or_else(|_| path.to_path_buf());
+    audit::record_denied(&resolved, access.as_str());
+}
+
+pub fn validate_path_or_error(path: &Path, access: Access) -> Result<ResolvedPath, String> {
+    let found = check_path_access(path, access).inspect_err(|_| record_denied(path, access))?;
+    audit::record_access(&found.resolved, access.as_str());
+    Ok(found.into())
+}
+
+/// Like `validate_path_or_error` for tools that walk a tree, without recording the access of
+/// every file they look at
+pub fn resolve_if_allowed(path: &Path, access: Access) -> Option<ResolvedPath> {
+    check_path_access(path, access).ok().map(ResolvedPath::from)
+}
+
+/// Check the size of file content being read or written against the limit of the path's root
+pub fn validate_file_size_or_error(path: &Path, size: u64) -> Result<(), String> {
+    let Ok(Some(found)) = find_root(path) else {
+        return Ok(()); // access itself is checked by validate_path_or_error
+    };
+    match found.root.max_file_size {
+        Some(limit) if size > limit => Err(format!(
+            "Access denied: {} bytes for {} exceeds the maximum file size of {} bytes",
+            size,
+            path.display(),
+            limit
+        )),
+        _ => Ok(()),
+    }
+}
+
+// For operations that involve two paths (like move/rename)
+pub fn validate_paths_or_error(
+    source: &Path,
+    source_access: Access,
+    target: &Path,
+    target_access: Access,
+) -> Result<(ResolvedPath, ResolvedPath), String> {
+    let source = check_path_access(source, source_access).map_err(|e| {
+        record_denied(source, source_access);
+        format!("Source path: {}", e)
+    })?;
+    let target = check_path_access(target, target_access).map_err(|e| {
+        record_denied(target, target_access);
+        format!("Target path: {}", e)
+    })?;
+    audit::record_access(&source.resolved, source_access.as_str());
+    audit::record_access(&target.resolved, target_access.as_str());
+    Ok((source.into(), target.into()))
+}
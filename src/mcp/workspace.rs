@@ -0,0 +1,59 @@
+//! The workspace root that relative paths in tool requests are resolved against, and that
+//! paths in responses are shown relative to.
+
+use crate::mcp::paths;
+use crate::mcp::session;
+use crate::mcp::utilities::get_allowed_directories;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// set by the `set_working_directory` tool over stdio; sessions keep their own
+static WORKING_DIRECTORY: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// The current workspace root: the directory chosen with `set_working_directory`,
+/// or the first allowed directory.
+pub fn root() -> Option<PathBuf> {
+    let working_directory = session::with_state(|state| state.working_directory.clone())
+        .unwrap_or_else(|| WORKING_DIRECTORY.read().unwrap_or_else(|e| e.into_inner()).clone());
+    if let Some(dir) = working_directory {
+        return Some(dir);
+    }
+    get_allowed_directories()
+        .first()
+        .map(|dir| Path::new(dir).canonicalize().unwrap_or_else(|_| PathBuf::from(dir)))
+}
+
+/// Change the workspace root, or go back to the default with `None`
+pub fn set_root(dir: Option<PathBuf>) {
+    match session::current() {
+        Some(session) => session.state().working_directory = dir,
+        None => *WORKING_DIRECTORY.write().unwrap_or_else(|e| e.into_inner()) = dir,
+    }
+}
+
+/// Turn a path from a tool request into an absolute path. Relative paths are joined to the
+/// workspace root; without one, they are returned unchanged and fail validation.
+pub fn resolve(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    match root() {
+        Some(root) if path.is_relative() => root.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Show `path` relative to the workspace root when it lies inside it, to keep responses short
+pub fn display(path: &Path) -> String {
+    let Some(root) = root() else {
+        return path.display().to_string();
+    };
+    let relative = path.strip_prefix(&root).ok().map(Path::to_path_buf).or_else(|| {
+        let resolved = paths::resolve_path(path).ok()?;
+        resolved.strip_prefix(&root).ok().map(Path::to_path_buf)
+    });
+    match relative {
+        Some(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+        Some(relative) => relative.display().to_string(),
+        None => path.display().to_string(),
+    }
+}
@@ -0,0 +1,300 @@
+//! Replay of a recorded session: the messages a client sent, read back from the server log, are
+//! handled again against a copy of the directory tree the session ran on, and the responses are
+//! compared with the recorded ones. This turns real sessions into regression tests.
+//!
+//! Some results depend on the run rather than on the tree: `get_local_time` isn't compared, and
+//! git commit ids are masked. Modification times of files the session itself wrote still differ.
+
+use crate::server::FilesystemServer;
+use regex::Regex;
+use serde_json::Value;
+use std::fs;
+use std::fs::File;
+use std::fs::Metadata;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// tools whose results depend on the clock, so their responses are never the same twice
+const CLOCK_TOOLS: &[&str] = &["get_local_time"];
+
+/// git commit ids, which depend on the time of the commit
+static COMMIT_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b[0-9a-f]{40}\b").expect("commit id pattern is valid"));
+
+/// A message the client sent, with the response the server recorded for it, if any
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exchange {
+    /// line of the message in the log, from 1
+    pub line: usize,
+    pub message: Value,
+    pub response: Option<Value>,
+}
+
+/// A response that differs from the recorded one
+#[derive(Debug, Clone)]
+pub struct Difference {
+    pub line: usize,
+    pub message: Value,
+    pub recorded: Value,
+    /// `None` when the server no longer responds
+    pub replayed: Option<Value>,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// messages sent to the server
+    pub replayed: usize,
+    /// responses compared with a recorded one, which leaves out those that depend on the clock
+    pub compared: usize,
+    pub differences: Vec<Difference>,
+}
+
+/// Read the client's messages and the server's responses from a server log. Responses are
+/// written followed by an empty line, which tells them apart from the client's answers to server
+/// requests. Events are skipped. A log with several clients at once is read as one session.
+pub fn read_log(log: &str) -> Vec<Exchange> {
+    let lines: Vec<&str> = log.lines().collect();
+    let mut exchanges: Vec<Exchange> = Vec::new();
+    // exchanges whose response hasn't been read yet
+    let mut waiting: Vec<usize> = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if message.get("event").is_some() && message.get("timestamp").is_some() {
+            continue;
+        }
+        if lines.get(index + 1) != Some(&"") || !is_response(&message) {
+            if expects_response(&message) {
+                waiting.push(exchanges.len());
+            }
+            exchanges.push(Exchange { line: index + 1, message, response: None });
+            continue;
+        }
+        // ids are reused by every client, so the response is for the latest request with its id
+        if let Some(position) = waiting.iter().rposition(|&i| answers(&message, &exchanges[i].message)) {
+            let i = waiting.remove(position);
+            exchanges[i].response = Some(message);
+        }
+    }
+    exchanges
+}
+
+fn is_response(message: &Value) -> bool {
+    match message {
+        Value::Array(items) => items.iter().all(|item| item.get("method").is_none()),
+        Value::Object(object) => !object.contains_key("method"),
+        _ => false,
+    }
+}
+
+fn expects_response(message: &Value) -> bool {
+    let is_request = |message: &Value| message.get("method").is_some() && message.get("id").is_some();
+    match message {
+        Value::Array(items) => items.iter().any(is_request),
+        message => is_request(message),
+    }
+}
+
+fn answers(response: &Value, request: &Value) -> bool {
+    match (response, request) {
+        (Value::Array(_), Value::Array(_)) => true,
+        (Value::Object(_), Value::Object(_)) => response.get("id") == request.get("id"),
+        _ => false,
+    }
+}
+
+/// Send the messages to `server` one at a time, with paths under `recorded_root` moved to
+/// `replay_root`, and compare the responses with the recorded ones, with the paths moved back
+pub async fn replay(server: &FilesystemServer, exchanges: &[Exchange], recorded_root: &Path, replay_root: &Path) -> Report {
+    let recorded_root = recorded_root.to_string_lossy();
+    let replay_root = replay_root.to_string_lossy();
+    let mut report = Report::default();
+    for exchange in exchanges {
+        let message = replace_paths(&exchange.message, &recorded_root, &replay_root);
+        let replayed = server
+            .handle(message)
+            .await
+            .map(|response| replace_paths(&response, &replay_root, &recorded_root));
+        report.replayed += 1;
+        let Some(recorded) = &exchange.response else {
+            continue;
+        };
+        if is_clock_dependent(&exchange.message) {
+            continue;
+        }
+        report.compared += 1;
+        if replayed.as_ref().map(mask_commit_ids) != Some(mask_commit_ids(recorded)) {
+            report.differences.push(Difference {
+                line: exchange.line,
+                message: exchange.message.clone(),
+                recorded: recorded.clone(),
+                replayed,
+            });
+        }
+    }
+    report
+}
+
+fn is_clock_dependent(message: &Value) -> bool {
+    message["method"] == "tools/call" && CLOCK_TOOLS.iter().any(|tool| message["params"]["name"] == *tool)
+}
+
+fn mask_commit_ids(value: &Value) -> Value {
+    map_strings(value, &|string| COMMIT_ID.replace_all(string, "<commit>").into_owned())
+}
+
+/// Replace the path `from` with `to` in every string of `value`, which covers paths and
+/// `file://` URIs. Only whole paths are replaced: with `from` being `/a/b`, `/a/b/c` becomes
+/// `/x/c` but `/a/bc` and `/c/a/b` are left alone.
+fn replace_paths(value: &Value, from: &str, to: &str) -> Value {
+    map_strings(value, &|string| replace_path(string, from, to))
+}
+
+fn replace_path(string: &str, from: &str, to: &str) -> String {
+    let is_name = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    let mut replaced = String::with_capacity(string.len());
+    let mut copied = 0;
+    for (start, _) in string.match_indices(from) {
+        let end = start + from.len();
+        if from.is_empty() || is_name(string[..start].chars().next_back()) || is_name(string[end..].chars().next()) {
+            continue;
+        }
+        replaced.push_str(&string[copied..start]);
+        replaced.push_str(to);
+        copied = end;
+    }
+    replaced.push_str(&string[copied..]);
+    replaced
+}
+
+fn map_strings(value: &Value, f: &impl Fn(&str) -> String) -> Value {
+    match value {
+        Value::String(string) => Value::String(f(string)),
+        Value::Array(items) => Value::Array(items.iter().map(|item| map_strings(item, f)).collect()),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| (key.clone(), map_strings(value, f)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Copy the directory tree at `from` into `to`, keeping modification times so that listings and
+/// file info match the recording. Symbolic links are copied as links.
+pub fn copy_tree(from: &Path, to: &Path) -> Result<(), String> {
+    fs::create_dir_all(to).map_err(|e| format!("Failed to create {}: {}", to.display(), e))?;
+    let entries = fs::read_dir(from).map_err(|e| format!("Failed to read {}: {}", from.display(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", from.display(), e))?;
+        let source = entry.path();
+        let target = to.join(entry.file_name());
+        let metadata = fs::symlink_metadata(&source).map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+        if metadata.is_symlink() {
+            copy_link(&source, &target)?;
+            continue;
+        }
+        if metadata.is_dir() {
+            copy_tree(&source, &target)?;
+        } else {
+            fs::copy(&source, &target).map_err(|e| format!("Failed to copy {}: {}", source.display(), e))?;
+        }
+        keep_modified(&target, &metadata);
+    }
+    if let Ok(metadata) = fs::metadata(from) {
+        keep_modified(to, &metadata);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_link(source: &Path, target: &Path) -> Result<(), String> {
+    let link = fs::read_link(source).map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+    std::os::unix::fs::symlink(link, target).map_err(|e| format!("Failed to copy {}: {}", source.display(), e))
+}
+
+#[cfg(not(unix))]
+fn copy_link(_source: &Path, _target: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// Best effort: a different time only shows up as a difference in the report
+fn keep_modified(path: &Path, metadata: &Metadata) {
+    if let (Ok(modified), Ok(file)) = (metadata.modified(), File::open(path)) {
+        let _ = file.set_modified(modified);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_log_pairs_responses_with_requests() {
+        let log = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2025-06-18"}}"#,
+            "",
+            r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+            r#"{"timestamp":"2025-01-01T00:00:00Z","event":"client_roots_updated","roots":[]}"#,
+            r#"{"jsonrpc":"2.0","id":"server-1","result":{"roots":[]}}"#,
+            "not json",
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"slow"}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"ping"}"#,
+            r#"{"jsonrpc":"2.0","id":3,"result":{}}"#,
+            "",
+            r#"[{"jsonrpc":"2.0","id":4,"method":"ping"}]"#,
+            r#"[{"jsonrpc":"2.0","id":4,"result":{}}]"#,
+            "",
+            r#"{"jsonrpc":"2.0","id":2,"result":{"content":[]}}"#,
+            "",
+            // a new client starts over with id 1
+            r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#,
+            r#"{"jsonrpc":"2.0","id":1,"result":{}}"#,
+            "",
+            r#"{"jsonrpc":"2.0","id":5,"method":"ping"}"#,
+        ]
+        .join("\n");
+        let exchanges = read_log(&log);
+        let summary: Vec<(usize, bool)> = exchanges.iter().map(|exchange| (exchange.line, exchange.response.is_some())).collect();
+        assert_eq!(
+            summary,
+            vec![(1, true), (4, false), (6, false), (8, true), (9, true), (12, true), (17, true), (20, false)]
+        );
+        assert_eq!(exchanges[3].response.as_ref().unwrap()["result"], json!({"content": []}));
+        assert!(exchanges[5].response.as_ref().unwrap().is_array());
+    }
+
+    #[test]
+    fn test_copy_tree_keeps_contents_and_times() {
+        let temp_dir = TempDir::new().unwrap();
+        let from = temp_dir.path().join("from");
+        fs::create_dir_all(from.join("src")).unwrap();
+        fs::write(from.join("src/main.rs"), "fn main() {}\n").unwrap();
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        File::options().write(true).open(from.join("src/main.rs")).unwrap().set_modified(old).unwrap();
+
+        let to = temp_dir.path().join("to");
+        copy_tree(&from, &to).unwrap();
+        assert_eq!(fs::read_to_string(to.join("src/main.rs")).unwrap(), "fn main() {}\n");
+        assert_eq!(fs::metadata(to.join("src/main.rs")).unwrap().modified().unwrap(), old);
+
+        let moved = replace_paths(&json!({"uri": "file:///a/b/c", "list": ["/a/b"], "n": 1}), "/a/b", "/x");
+        assert_eq!(moved, json!({"uri": "file:///x/c", "list": ["/x"], "n": 1}));
+        let text = json!("Moved /a/b/c to /a/bc, next to /c/a/b and /a/b.txt: /a/b");
+        assert_eq!(replace_paths(&text, "/a/b", "/x"), json!("Moved /x/c to /a/bc, next to /c/a/b and /a/b.txt: /x"));
+    }
+
+    #[test]
+    fn test_run_dependent_results_are_not_compared() {
+        let commit = |id: &str| json!({"result": {"content": [{"text": format!("Patch applied. Changes committed to git ({})", id)}]}});
+        let first = commit("3f786850e387550fdab836ed7e6dc881de23001b");
+        let second = commit("89e6c98d92887913cadf06b2adb97f26cde4849b");
+        assert_eq!(mask_commit_ids(&first), mask_commit_ids(&second));
+        assert!(is_clock_dependent(&json!({"method": "tools/call", "params": {"name": "get_local_time"}})));
+        assert!(!is_clock_dependent(&json!({"method": "tools/call", "params": {"name": "read_file"}})));
+    }
+}
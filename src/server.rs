@@ -0,0 +1,271 @@
+//! The embeddable server: a builder for the roots, policies, transport and extra tools, and the
+//! transports' entry points. The binary is a CLI over this.
+
+use crate::mcp::audit;
+use crate::mcp::config;
+use crate::mcp::config::AccessMode;
+use crate::mcp::config::Config;
+use crate::mcp::config::RootConfig;
+use crate::mcp::http;
+use crate::mcp::logging::logging_set_level;
+use crate::mcp::output;
+use crate::mcp::prompts::prompts_get;
+use crate::mcp::prompts::prompts_list;
+use crate::mcp::resources::allowed_directories;
+use crate::mcp::resources::resource_read;
+use crate::mcp::resources::resource_templates_list;
+use crate::mcp::resources::resources_list;
+use crate::mcp::rpc;
+use crate::mcp::rpc::Dispatcher;
+use crate::mcp::server_log;
+use crate::mcp::subscriptions;
+use crate::mcp::subscriptions::resources_subscribe;
+use crate::mcp::subscriptions::resources_unsubscribe;
+use crate::mcp::tool_registry::ToolDefinition;
+use crate::mcp::tools;
+use crate::mcp::tools::register_tools;
+#[cfg(unix)]
+use crate::mcp::unix_socket;
+use crate::mcp::utilities::initialize;
+use crate::mcp::utilities::ping;
+use rpc_router::Handler;
+use rpc_router::Router;
+use rpc_router::RouterBuilder;
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::io::AsyncBufReadExt;
+use tokio::task::JoinSet;
+
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 16;
+
+/// How clients reach the server
+#[derive(Debug, Clone, Default)]
+pub enum Transport {
+    /// one client, over stdin and stdout
+    #[default]
+    Stdio,
+    /// Streamable HTTP on a port or host:port, with an optional bearer token
+    Http { address: String, token: Option<String> },
+    /// a Unix domain socket with the given file permissions, one session per connection
+    #[cfg(unix)]
+    UnixSocket { path: PathBuf, mode: u32 },
+}
+
+pub struct FilesystemServer {
+    dispatcher: Dispatcher,
+    transport: Transport,
+}
+
+#[derive(Default)]
+pub struct FilesystemServerBuilder {
+    config_file: Option<PathBuf>,
+    config: Option<Config>,
+    read_only: bool,
+    redact_secrets: bool,
+    env_roots: Option<bool>,
+    log_file: Option<PathBuf>,
+    audit_log_file: Option<PathBuf>,
+    transport: Transport,
+    max_concurrent_requests: Option<usize>,
+    tools: Vec<&'static ToolDefinition>,
+}
+
+impl FilesystemServerBuilder {
+    /// Load roots and policies from a TOML config file, re-read when it changes. Without a
+    /// config file or roots set here, the `MCP_RS_FILESYSTEM_CONFIG` env variable names one.
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Use roots and policies set in code instead of a config file
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Allow access to `path`
+    pub fn root(mut self, path: impl Into<PathBuf>, mode: AccessMode) -> Self {
+        self.config.get_or_insert_with(Config::default).roots.push(RootConfig {
+            path: path.into(),
+            mode,
+            deny: Vec::new(),
+            max_file_size: None,
+        });
+        self
+    }
+
+    /// Never modify disk: mutating tools are hidden and refused
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Mask secrets in `read_file` and `grep_search` output
+    pub fn redact_secrets(mut self, redact_secrets: bool) -> Self {
+        self.redact_secrets = redact_secrets;
+        self
+    }
+
+    /// Also allow the directories in the `MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES` env variable, with
+    /// full access. On by default, except when roots are set in code with `root` or `config`.
+    pub fn env_roots(mut self, env_roots: bool) -> Self {
+        self.env_roots = Some(env_roots);
+        self
+    }
+
+    /// Write the server log to `path` instead of `MCP_LOG_FILE_PATH` or the default location
+    pub fn log_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_file = Some(path.into());
+        self
+    }
+
+    /// Write the audit log to `path` instead of `MCP_AUDIT_LOG_PATH` or the default location
+    pub fn audit_log_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.audit_log_file = Some(path.into());
+        self
+    }
+
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = Some(max_concurrent_requests);
+        self
+    }
+
+    /// Offer `tool` next to the filesystem tools. Tools that aren't read-only by their hints
+    /// are hidden and refused in read-only mode.
+    pub fn tool(mut self, tool: &'static ToolDefinition) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    /// Apply the settings and set up the router. The settings and extra tools are process-wide:
+    /// building another server replaces them, also for servers built before. A build that fails
+    /// changes nothing.
+    pub fn build(self) -> Result<FilesystemServer, String> {
+        tools::check_extra_tools(&self.tools)?;
+        let env_roots = self.env_roots.unwrap_or(self.config.is_none());
+        match (self.config_file, self.config) {
+            (Some(_), Some(_)) => return Err("Set roots either in a config file or in code, not both".to_string()),
+            (None, Some(config)) => config::set(config)?,
+            (path, None) => config::init(path)?,
+        }
+        config::set_env_roots(env_roots);
+        server_log::set_log_path(self.log_file);
+        audit::set_audit_log_path(self.audit_log_file);
+        config::set_read_only(self.read_only);
+        config::set_redact_secrets(self.redact_secrets);
+        tools::set_extra_tools(&self.tools)?;
+        let max_concurrent_requests = self.max_concurrent_requests.unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS);
+        Ok(FilesystemServer {
+            dispatcher: Dispatcher::new(build_rpc_router(), max_concurrent_requests),
+            transport: self.transport,
+        })
+    }
+}
+
+impl FilesystemServer {
+    pub fn builder() -> FilesystemServerBuilder {
+        FilesystemServerBuilder::default()
+    }
+
+    /// Handle a JSON-RPC message or batch from a client in this process, returning the response
+    /// if there is one. Notifications and requests the server sends meanwhile go to stdout.
+    pub async fn handle(&self, message: Value) -> Option<Value> {
+        self.dispatcher.dispatch(message).await
+    }
+
+    /// Watch the allowed directories and serve clients on the transport until it closes: for
+    /// stdio when stdin is closed, otherwise until the process exits
+    pub async fn serve(self) -> Result<(), String> {
+        subscriptions::start();
+        match self.transport {
+            Transport::Stdio => {
+                serve_stdio(self.dispatcher).await;
+                Ok(())
+            }
+            Transport::Http { address, token } => http::serve(&address, token, self.dispatcher).await,
+            #[cfg(unix)]
+            Transport::UnixSocket { path, mode } => unix_socket::serve(&path, mode, self.dispatcher).await,
+        }
+    }
+}
+
+fn build_rpc_router() -> Router {
+    let builder = RouterBuilder::default()
+        // append resources here
+        .append_dyn("initialize", initialize.into_dyn())
+        .append_dyn("ping", ping.into_dyn())
+        .append_dyn("logging/setLevel", logging_set_level.into_dyn())
+        .append_dyn("prompts/list", prompts_list.into_dyn())
+        .append_dyn("prompts/get", prompts_get.into_dyn())
+        .append_dyn("resources/list", resources_list.into_dyn())
+        .append_dyn("resources/read", resource_read.into_dyn())
+        .append_dyn("resources/templates/list", resource_templates_list.into_dyn())
+        .append_dyn("resources/subscribe", resources_subscribe.into_dyn())
+        .append_dyn("resources/unsubscribe", resources_unsubscribe.into_dyn())
+        .append_dyn("resources/allowed_directories", allowed_directories.into_dyn());
+    let builder = register_tools(builder);
+    builder.build()
+}
+
+/// Read JSON-RPC messages from stdin, one per line, and write the responses to stdout
+async fn serve_stdio(dispatcher: Dispatcher) {
+    output::start_stdout_writer();
+    let log_path = server_log::log_path();
+
+    // logging must not stop the server, e.g. when the log directory doesn't exist
+    let mut logging_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .ok();
+
+    // lines are split as bytes, so that invalid UTF-8 gets a parse error instead of ending the loop
+    let mut reader = tokio::io::BufReader::new(tokio::io::stdin()).split(b'\n');
+    let mut requests = JoinSet::new();
+
+    while let Ok(Some(line)) = reader.next_segment().await {
+        while requests.try_join_next().is_some() {}
+        if let Some(logging_file) = logging_file.as_mut() {
+            let _ = writeln!(logging_file, "{}", String::from_utf8_lossy(&line));
+        }
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let Ok(json_value) = serde_json::from_slice::<Value>(&line) else {
+            respond(&rpc::parse_error());
+            continue;
+        };
+        let is_initialize = json_value.get("method").and_then(Value::as_str) == Some("initialize");
+        let response = dispatcher.dispatch(json_value);
+        if is_initialize {
+            // The negotiated protocol version applies to everything after it
+            if let Some(response) = response.await {
+                respond(&response);
+            }
+            continue;
+        }
+        // Each request runs on its own task so that a slow tool call doesn't hold up the others
+        requests.spawn(async move {
+            if let Some(response) = response.await {
+                respond(&response);
+            }
+        });
+    }
+
+    // stdin was closed: let the requests in flight finish and send their responses
+    while requests.join_next().await.is_some() {}
+    output::flush().await;
+}
+
+fn respond(response: &Value) {
+    server_log::log_message(response);
+    output::send(response);
+}
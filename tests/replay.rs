@@ -0,0 +1,110 @@
+use rs_filesystem::mcp::config::AccessMode;
+use rs_filesystem::replay;
+use rs_filesystem::FilesystemServer;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+use tempfile::TempDir;
+
+fn call(id: u64, tool: &str, arguments: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "tools/call", "params": {"name": tool, "arguments": arguments}})
+}
+
+/// Run the server over stdio with `allowed` as its allowed directories and send it `messages`,
+/// each once the previous one is answered, so that the log it writes is a real recording
+fn record(messages: &[Value], allowed: &Path, log: &Path) {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_rs_filesystem"))
+        .arg("--mcp")
+        .env("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", allowed)
+        .env("MCP_LOG_FILE_PATH", log)
+        .env("MCP_AUDIT_LOG_PATH", log.with_extension("audit.jsonl"))
+        .env_remove("MCP_RS_FILESYSTEM_CONFIG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = BufReader::new(server.stdout.take().unwrap()).lines();
+    for message in messages {
+        writeln!(stdin, "{}", message).unwrap();
+        let Some(id) = message.get("id") else {
+            continue;
+        };
+        // skip notifications until the response comes
+        for line in stdout.by_ref() {
+            let line: Value = serde_json::from_str(&line.unwrap()).unwrap();
+            if line.get("id") == Some(id) && line.get("method").is_none() {
+                break;
+            }
+        }
+    }
+    drop(stdin);
+    assert!(server.wait().unwrap().success());
+}
+
+#[tokio::test]
+async fn test_recorded_session_replays_on_a_copy() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path().canonicalize().unwrap();
+    let tree = base.join("tree");
+    fs::create_dir_all(tree.join("src")).unwrap();
+    fs::write(tree.join("notes.txt"), "hello\n").unwrap();
+    fs::write(tree.join("src/main.rs"), "fn main() {}\n").unwrap();
+    let outside = base.join("outside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("secret.txt"), "secret\n").unwrap();
+    let snapshot = base.join("snapshot");
+    replay::copy_tree(&tree, &snapshot).unwrap();
+
+    let notes = tree.join("notes.txt");
+    let messages = [
+        json!({"jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {
+            "protocolVersion": "2025-06-18",
+            "capabilities": {},
+            "clientInfo": {"name": "recorder", "version": "1.0"},
+        }}),
+        json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+        call(1, "read_file", json!({"file_path": notes})),
+        call(2, "file_edit", json!({"file_path": notes, "old_content": "hello", "new_content": "bye", "commit_message": "edit"})),
+        call(3, "get_file_info", json!({"path": tree.join("src/main.rs")})),
+        call(4, "get_local_time", json!({})),
+        call(5, "read_file", json!({"file_path": outside.join("secret.txt")})),
+        json!({"jsonrpc": "2.0", "method": "notifications/cancelled", "params": {"requestId": 9}}),
+    ];
+    let log_path = base.join("rs_filesystem.logs.jsonl");
+    record(&messages, &tree, &log_path);
+    assert_eq!(fs::read_to_string(&notes).unwrap(), "bye\n");
+
+    // the replay's own logs go elsewhere, and the allowed directories in the env variable don't
+    // apply: the read outside of the tree is still refused
+    std::env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", &outside);
+    let copy = base.join("copy");
+    replay::copy_tree(&snapshot, &copy).unwrap();
+    let server = FilesystemServer::builder()
+        .root(&copy, AccessMode::ReadWrite)
+        .log_file(base.join("replay.logs.jsonl"))
+        .audit_log_file(base.join("replay.audit.jsonl"))
+        .build()
+        .unwrap();
+
+    // on a copy of the snapshot, the session comes out the same, apart from the clock
+    let exchanges = replay::read_log(&fs::read_to_string(&log_path).unwrap());
+    let report = replay::replay(&server, &exchanges, &tree, &copy).await;
+    assert_eq!((report.replayed, report.compared), (8, 5));
+    assert!(report.differences.is_empty(), "{:?}", report.differences);
+    assert_eq!(fs::read_to_string(copy.join("notes.txt")).unwrap(), "bye\n");
+    assert_eq!(fs::read_to_string(&notes).unwrap(), "bye\n");
+
+    // on the edited copy the read and the edit come out differently
+    let report = replay::replay(&server, &exchanges, &tree, &copy).await;
+    let ids: Vec<&Value> = report.differences.iter().map(|difference| &difference.message["id"]).collect();
+    assert_eq!(ids, vec![&json!(1), &json!(2)]);
+    assert_eq!(report.differences[1].replayed.as_ref().unwrap()["result"]["isError"], json!(true));
+    assert!(base.join("replay.audit.jsonl").exists());
+}
@@ -0,0 +1,135 @@
+use rs_filesystem::mcp::config::AccessMode;
+use rs_filesystem::mcp::types::CallToolResult;
+use rs_filesystem::mcp::types::CallToolResultContent;
+use rs_filesystem::rpc_router::Handler;
+use rs_filesystem::rpc_router::HandlerResult;
+use rs_filesystem::rpc_router::RpcParams;
+use rs_filesystem::schema_for;
+use rs_filesystem::Access;
+use rs_filesystem::FilesystemServer;
+use rs_filesystem::ToolDefinition;
+use rs_filesystem::ToolHints;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+#[derive(Deserialize, JsonSchema, RpcParams)]
+struct ShoutRequest {
+    /// Text to shout
+    text: String,
+}
+
+async fn shout(request: ShoutRequest) -> HandlerResult<CallToolResult> {
+    Ok(CallToolResult {
+        content: vec![CallToolResultContent::Text { text: request.text.to_uppercase() }],
+        is_error: false,
+        ..Default::default()
+    })
+}
+
+static SHOUT: ToolDefinition = ToolDefinition {
+    name: "shout",
+    title: "Shout",
+    description: "Repeat the text in capitals",
+    hints: ToolHints::READ_ONLY,
+    input_schema: schema_for::<ShoutRequest>,
+    output_schema: None,
+    register: |builder, name| builder.append_dyn(name, shout.into_dyn()),
+};
+
+static PING: ToolDefinition = ToolDefinition {
+    name: "ping",
+    title: "Ping",
+    description: "Shout under the name of a server method",
+    hints: ToolHints::READ_ONLY,
+    input_schema: schema_for::<ShoutRequest>,
+    output_schema: None,
+    register: |builder, name| builder.append_dyn(name, shout.into_dyn()),
+};
+
+fn call(id: u64, tool: &str, arguments: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "method": "tools/call", "params": {"name": tool, "arguments": arguments}})
+}
+
+fn text(response: &Value) -> &str {
+    response["result"]["content"][0]["text"].as_str().unwrap()
+}
+
+#[tokio::test]
+async fn test_embedded_server_with_an_extra_tool() {
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path().canonicalize().unwrap();
+    fs::write(root.join("notes.txt"), "hello\n").unwrap();
+    // roots set in code are the only ones: the env variable is ignored
+    let elsewhere = TempDir::new().unwrap();
+    std::env::set_var("MCP_RS_FILESYSTEM_ALLOWED_DIRECTORIES", elsewhere.path());
+    let server = FilesystemServer::builder()
+        .root(&root, AccessMode::ReadOnly)
+        .tool(&SHOUT)
+        .build()
+        .unwrap();
+
+    let initialize = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+        "protocolVersion": "2025-06-18",
+        "capabilities": {},
+        "clientInfo": {"name": "host", "version": "1.0"},
+    }});
+    let response = server.handle(initialize).await.unwrap();
+    assert_eq!(response["result"]["serverInfo"]["name"], json!("rs_filesystem"));
+
+    let response = server.handle(json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"})).await.unwrap();
+    let names: Vec<&str> = response["result"]["tools"].as_array().unwrap().iter().map(|tool| tool["name"].as_str().unwrap()).collect();
+    assert!(names.contains(&"read_file") && names.last() == Some(&"shout"));
+
+    let response = server.handle(call(3, "shout", json!({"text": "hi"}))).await.unwrap();
+    assert_eq!(text(&response), "HI");
+    let response = server.handle(call(4, "shout", json!({}))).await.unwrap();
+    assert_eq!(response["error"]["code"], json!(-32602));
+
+    let notes = root.join("notes.txt");
+    let response = server.handle(call(5, "read_file", json!({"file_path": notes}))).await.unwrap();
+    assert!(text(&response).contains("hello"));
+    let response = server.handle(call(6, "read_file", json!({"file_path": "/etc/hostname"}))).await.unwrap();
+    assert_eq!(response["result"]["isError"], json!(true));
+    let response = server.handle(call(7, "overwrite_file", json!({"path": notes, "content": "bye"}))).await.unwrap();
+    assert_eq!(response["result"]["isError"], json!(true));
+    assert_eq!(fs::read_to_string(&notes).unwrap(), "hello\n");
+
+    // the same policy checks are available to the host
+    assert!(rs_filesystem::is_path_allowed(&notes, Access::Read));
+    assert!(!rs_filesystem::is_path_allowed(&notes, Access::Write));
+    assert!(!rs_filesystem::is_path_allowed(Path::new("/etc/hostname"), Access::Read));
+    assert!(!rs_filesystem::is_path_allowed(&elsewhere.path().join("notes.txt"), Access::Read));
+
+    // every server method is routed, and no tool can take its name
+    for (id, method) in rs_filesystem::mcp::rpc::METHODS.iter().enumerate() {
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": {}});
+        let response = server.handle(request).await.unwrap();
+        assert_ne!(response["error"]["code"], json!(-32601), "{}", method);
+    }
+    assert!(rs_filesystem::mcp::tools::check_extra_tools(&[&PING]).unwrap_err().contains("server method"));
+
+    // tool names stay unique, and a build that fails leaves the server as it was
+    assert!(FilesystemServer::builder().tool(&SHOUT).tool(&SHOUT).build().is_err());
+    let response = server.handle(call(8, "read_file", json!({"file_path": notes}))).await.unwrap();
+    assert!(text(&response).contains("hello"));
+
+    // building again replaces the extra tools instead of adding to them
+    let rebuilt = FilesystemServer::builder().root(&root, AccessMode::ReadOnly).tool(&SHOUT).build().unwrap();
+    let response = rebuilt.handle(json!({"jsonrpc": "2.0", "id": 9, "method": "tools/list"})).await.unwrap();
+    let tools = response["result"]["tools"].as_array().unwrap();
+    assert_eq!(tools.iter().filter(|tool| tool["name"] == "shout").count(), 1);
+}
+
+#[test]
+fn test_roots_come_from_one_place() {
+    let result = FilesystemServer::builder()
+        .config_file("/nonexistent/config.toml")
+        .root("/tmp", AccessMode::ReadWrite)
+        .build();
+    assert!(result.is_err());
+}